@@ -1,22 +1,253 @@
-use dialoguer::console::Color;
-use dialoguer::{theme::ColorfulTheme, Input, Select};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
 
-use std::{fs, str, string};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-
-use serde_json::{json, Number, Value};
+use std::{error::Error, fs, path::Path, sync::Arc};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{ClientConfig, ServerName},
+    TlsConnector,
+};
 
 use strum::IntoEnumIterator;
 
-use nnio_common::*;
+use nnio_common::{tls::*, *};
 
 #[macro_use]
 extern crate log;
 
-use log::debug;
+/// Sends a request and waits for the response carrying the same id
+async fn send_request(
+    stream: &mut Box<dyn AsyncStream>,
+    codec: &FrameCodec,
+    next_id: &mut u64,
+    req: Request,
+) -> Option<Response> {
+    let id = *next_id;
+    *next_id += 1;
+
+    if let Err(e) = codec.write_msg(stream, &RequestFrame { id, req }).await {
+        error!("Failed to send request : {}", e);
+        return None;
+    }
+
+    loop {
+        match codec.read_msg::<_, ResponseFrame>(stream).await {
+            Ok(Some(frame)) if frame.id == id => return Some(frame.resp),
+            Ok(Some(ResponseFrame {
+                resp: Response::RespServerShutdown,
+                ..
+            })) => {
+                warn!("Server is shutting down");
+            }
+            Ok(Some(frame)) => {
+                debug!(
+                    "Skipping response #{} : {}",
+                    frame.id,
+                    frame.resp.msg_type()
+                );
+            }
+            Ok(None) => {
+                error!("Server closed the connection");
+                return None;
+            }
+            Err(e) => {
+                error!("Failed to receive response : {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+async fn handshake(
+    stream: &mut Box<dyn AsyncStream>,
+    codec: &FrameCodec,
+    next_id: &mut u64,
+    credentials: Option<Credentials>,
+) -> Option<Vec<MessageType>> {
+    let hello = Request::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        credentials,
+    };
+
+    match send_request(stream, codec, next_id, hello).await? {
+        Response::RespWelcome {
+            protocol_version,
+            server_build,
+            supported,
+            authenticated_as,
+        } => {
+            info!(
+                "Connected to {} (protocol version {}) as {}",
+                server_build,
+                protocol_version,
+                authenticated_as.as_deref().unwrap_or("anonymous")
+            );
+            Some(supported)
+        }
+        Response::RespError { code, message, .. } => {
+            error!("Server rejected connection [code {}] : {}", code, message);
+            None
+        }
+        other => {
+            error!("Unexpected handshake response : {}", other.msg_type());
+            None
+        }
+    }
+}
+
+/// Asks for the certificates to use and starts a TLS session over `tcp`
+async fn connect_tls(tcp: TcpStream, host: &str) -> Result<TlsStream<TcpStream>, Box<dyn Error>> {
+    let ca_path: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("CA certificate file of the server")
+        .interact_text()
+        .unwrap();
+
+    let client_cert: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Client certificate file (empty for none)")
+        .allow_empty(true)
+        .interact_text()
+        .unwrap();
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(load_root_store(Path::new(&ca_path))?);
+
+    let cfg = if client_cert.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let client_key: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Client private key file")
+            .interact_text()
+            .unwrap();
+
+        builder.with_client_auth_cert(
+            load_certs(Path::new(&client_cert))?,
+            load_private_key(Path::new(&client_key))?,
+        )?
+    };
+
+    let server_name = ServerName::try_from(host)?;
+
+    Ok(TlsConnector::from(Arc::new(cfg))
+        .connect(server_name, tcp)
+        .await?)
+}
+
+fn input_credentials() -> Option<Credentials> {
+    let methods = ["None", "Token", "User and password"];
+
+    let method = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Authentication")
+        .default(0)
+        .items(&methods)
+        .interact()
+        .unwrap();
+
+    match method {
+        1 => Some(Credentials::Token(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Token")
+                .interact()
+                .unwrap(),
+        )),
+        2 => Some(Credentials::Password {
+            user: Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("User")
+                .interact_text()
+                .unwrap(),
+            password: Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Password")
+                .interact()
+                .unwrap(),
+        }),
+        _ => None,
+    }
+}
+
+fn input_text(prompt: &str) -> String {
+    Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact_text()
+        .unwrap()
+}
+
+fn input_job_id() -> u64 {
+    Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Enter job id")
+        .interact_text()
+        .unwrap()
+}
+
+fn read_samples() -> Result<Vec<Sample>, String> {
+    let samples_filepath: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Input filepath of json samples [{\"input\": [..], \"expected\": [..]}, ..]")
+        .interact_text()
+        .unwrap();
+
+    fs::read_to_string(samples_filepath)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+}
+
+fn input_column_list(prompt: &str) -> Vec<usize> {
+    loop {
+        let columns: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .interact_text()
+            .unwrap();
+
+        match columns.split(',').map(|c| c.trim().parse()).collect() {
+            Ok(columns) => return columns,
+            Err(e) => error!("Invalid column list : {}", e),
+        }
+    }
+}
 
-use std::str::FromStr;
+/// Raw file bytes sent per `UploadDatasetFile`, well below the frame limit once base64 encoded
+const FILE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Sends a local file for `ImportDataset`, returns its size on the server
+async fn upload_file(
+    stream: &mut Box<dyn AsyncStream>,
+    codec: &FrameCodec,
+    next_id: &mut u64,
+    name: &str,
+    file: &str,
+    path: &str,
+) -> Result<u64, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+
+    let mut size = 0;
+
+    // an empty file still has to create the file on the server
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&data[..]]
+    } else {
+        data.chunks(FILE_CHUNK_SIZE).collect()
+    };
+
+    for (chunk, bytes) in chunks.into_iter().enumerate() {
+        let req = Request::UploadDatasetFile {
+            name: name.to_owned(),
+            file: file.to_owned(),
+            chunk,
+            data: BASE64.encode(bytes),
+        };
+
+        match send_request(stream, codec, next_id, req).await {
+            Some(Response::RespUploadDatasetFile { size: received }) => size = received,
+            Some(Response::RespError { code, message, .. }) => {
+                return Err(format!("[code {}] {}", code, message))
+            }
+            Some(other) => return Err(format!("Unexpected response {}", other.msg_type())),
+            None => return Err("Connection lost".to_owned()),
+        }
+    }
+
+    Ok(size)
+}
 
 #[tokio::main]
 async fn main() {
@@ -25,172 +256,584 @@ async fn main() {
     info!("Welcome to nevermind_io client !");
 
     let server_addr: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Input server ip address and port like 127.0.0.1:5569")
+        .with_prompt(
+            "Input server ip address and port like 127.0.0.1:5569, or unix:/path/to/socket",
+        )
         .with_initial_text("127.0.0.1:5569")
         .interact_text()
         .unwrap();
 
-    let addr_split: Vec<&str> = server_addr.split(':').collect();
-
-    if addr_split.len() != 2 {
-        error!("Invalid address");
-        return;
-    }
+    let mut stream: Box<dyn AsyncStream> = if let Some(path) = server_addr.strip_prefix("unix:") {
+        match UnixStream::connect(path).await {
+            Ok(stream) => Box::new(stream),
+            Err(e) => {
+                error!("Couldn't connect to {} : {}", path, e);
+                return;
+            }
+        }
+    } else {
+        let addr_split: Vec<&str> = server_addr.split(':').collect();
 
-    let server_ip = String::from(addr_split[0]);
-    let server_port: u16 = u16::from_str(addr_split[1]).expect("Invalid port number");
+        if addr_split.len() != 2 || addr_split[1].parse::<u16>().is_err() {
+            error!("Invalid address");
+            return;
+        }
 
-    let mut stream = TcpStream::connect(server_addr)
-        .await
-        .expect("Couldn't connect to server");
+        let tcp = TcpStream::connect(&server_addr)
+            .await
+            .expect("Couldn't connect to server");
 
-    let mut cmds_v = Vec::with_capacity(15);
-    for i in MessageType::iter() {
-        let c = i.to_string();
+        let use_tls = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Use TLS ?")
+            .default(false)
+            .interact()
+            .unwrap();
 
-        if !c.contains("Resp") {
-            cmds_v.push(c);
+        if use_tls {
+            match connect_tls(tcp, addr_split[0]).await {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    error!("TLS connection failed : {}", e);
+                    return;
+                }
+            }
+        } else {
+            Box::new(tcp)
         }
-    }
+    };
+
+    let codec = FrameCodec::default();
+    let mut next_id = 1;
+
+    let credentials = input_credentials();
 
-    let mut buffer = [0; 8192];
+    let supported = match handshake(&mut stream, &codec, &mut next_id, credentials).await {
+        Some(supported) => supported,
+        None => return,
+    };
+
+    let cmds_v: Vec<MessageType> = MessageType::iter()
+        .filter(|t| *t == MessageType::Exit || supported.contains(t))
+        .collect();
+    let cmds_names: Vec<String> = cmds_v.iter().map(|t| t.to_string()).collect();
 
     loop {
         let cmd = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Choose command")
             .default(0)
-            .items(&cmds_v[..])
+            .items(&cmds_names[..])
             .interact()
             .unwrap();
 
-        if cmd == MessageType::LoadModel as usize {
-            break;
-        } else if cmd == MessageType::GetAvailableModels as usize {
-            let msg_req = json!({
-                "type": MessageType::GetAvailableModels as usize,
-            });
-
-            stream
-                .write_all(msg_req.to_string().as_bytes())
-                .await
-                .unwrap();
-
-            if let Ok(bytes_read) = stream.read(&mut buffer).await {
-                if let Ok(json_recv) = serde_json::from_slice::<Value>(&buffer[0..bytes_read]) {
-                    if let Some(json_obj) = json_recv.as_object() {
-                        if let Value::Array(list_mdls) = json_obj.get("available_mdls").unwrap() {
-                            println!("Available models : ");
-                            for (idx, i) in list_mdls.iter().enumerate() {
-                                println!("{} : {}", idx, i.as_str().unwrap());
-                            }
-                        }
+        let req = match cmds_v[cmd] {
+            MessageType::GetAvailableModels => Request::GetAvailableModels,
+            MessageType::GetLoadedModels => Request::GetLoadedModels,
+            MessageType::ModelInfo => Request::ModelInfo {
+                mdl_name: input_text("Enter model name"),
+            },
+            MessageType::CreateModel => {
+                let net_cfg_filepath: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Input filepath of network configuration")
+                    .interact_text()
+                    .unwrap();
+
+                let name = input_text("Input model name");
+
+                let net_cfg = match fs::read_to_string(net_cfg_filepath) {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        error!("Failed to read file : {}", e);
+                        continue;
                     }
+                };
+
+                let description: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Description")
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+
+                let tags: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Comma separated tags")
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+
+                Request::CreateModel {
+                    name,
+                    net_cfg,
+                    overwrite: false,
+                    description,
+                    tags: tags
+                        .split(',')
+                        .map(|t| t.trim().to_owned())
+                        .filter(|t| !t.is_empty())
+                        .collect(),
                 }
             }
-        } else if cmd == MessageType::ModelInfo as usize {
-            let mdl_name: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Enter loaded model name")
-                .interact_text()
-                .unwrap();
-
-            let msg_req = json!({
-                "type": MessageType::ModelInfo as usize,
-                "mdl_name": mdl_name,
-            });
-
-            stream
-                .write_all(msg_req.to_string().as_bytes())
-                .await
-                .unwrap();
+            MessageType::LoadModel => {
+                let mdl_name = input_text("Enter model name");
+
+                let checkpoint: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Checkpoint to restore (empty for none)")
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+
+                Request::LoadModel {
+                    mdl_name,
+                    checkpoint: Some(checkpoint.trim().to_owned()).filter(|c| !c.is_empty()),
+                }
+            }
+            MessageType::SaveModelState => {
+                let mdl_name = input_text("Enter loaded model name");
 
-            if let Ok(bytes_read) = stream.read(&mut buffer).await {
-                let json_recv: Value =
-                    serde_json::from_slice(&buffer[0..bytes_read]).expect("Failed to parse json");
+                let checkpoint: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Checkpoint name")
+                    .interact_text()
+                    .unwrap();
 
-                if let Some(json_obj) = json_recv.as_object() {
-                    if !json_obj.contains_key("mdl_info") {
-                        warn!("Failed to retrieve model {} info!", mdl_name);
-                        continue;
+                Request::SaveModelState {
+                    mdl_name,
+                    checkpoint,
+                }
+            }
+            MessageType::ListCheckpoints => Request::ListCheckpoints {
+                mdl_name: input_text("Enter model name"),
+            },
+            MessageType::SaveModelCfg => Request::SaveModelCfg {
+                mdl_name: input_text("Enter model name"),
+            },
+            MessageType::TrainModel | MessageType::SubmitTrainJob => {
+                let mdl_name = input_text("Enter loaded model name");
+
+                let use_dataset = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Train on a stored dataset?")
+                    .default(false)
+                    .interact()
+                    .unwrap();
+
+                let (data, samples_count) = if use_dataset {
+                    (TrainData::Dataset(input_text("Enter dataset name")), 32)
+                } else {
+                    match read_samples() {
+                        Ok(samples) => {
+                            let count = samples.len();
+                            (TrainData::Samples(samples), count)
+                        }
+                        Err(e) => {
+                            error!("Failed to read samples : {}", e);
+                            continue;
+                        }
                     }
-
-                    if let Value::String(mdl_info) = json_obj.get("mdl_info").unwrap() {
-                        info!("Model {} | Info : {}", mdl_name, mdl_info);
+                };
+
+                let epochs: usize = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Epochs")
+                    .default(10)
+                    .interact_text()
+                    .unwrap();
+
+                let batch_size: usize = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Batch size")
+                    .default(samples_count.clamp(1, 32))
+                    .interact_text()
+                    .unwrap();
+
+                let learning_rate: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Learning rate (empty to keep the current one)")
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+
+                let params = TrainParams {
+                    epochs,
+                    batch_size,
+                    learning_rate: learning_rate.trim().parse().ok(),
+                };
+
+                if cmds_v[cmd] == MessageType::SubmitTrainJob {
+                    Request::SubmitTrainJob {
+                        mdl_name,
+                        data,
+                        params,
+                    }
+                } else {
+                    Request::TrainModel {
+                        mdl_name,
+                        data,
+                        params,
                     }
                 }
             }
-        } else if cmd == MessageType::CreateModel as usize {
-            let net_cfg_filepath: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Input filepath of network configuration")
-                .interact_text()
-                .unwrap();
-
-            let mdl_name: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Input model name")
-                .interact_text()
-                .unwrap();
-
-            let cfg_file = fs::read_to_string(net_cfg_filepath).expect("Failed to read file");
-
-            let msg = json!({
-                "type": MessageType::CreateModel as usize,
-                "net_cfg": cfg_file,
-                "name": mdl_name,
-            });
-
-            let msg_serialized = serde_json::to_string_pretty(&msg).unwrap();
-            std::fs::write("debug.cfg", msg_serialized.clone()).unwrap();
-
-            stream.write_all(msg_serialized.as_bytes()).await.unwrap();
-
-            if let Ok(bytes_read) = stream.read(&mut buffer).await {
-                if let Ok(json_recv) = serde_json::from_slice(&buffer[0..bytes_read]) {
-                    if let Value::Object(m) = json_recv {
-                        let msg_resp = m.get("type").unwrap();
-
-                        if let Value::Number(msg_resp) = msg_resp {
-                            if msg_resp.as_u64().unwrap()
-                                == MessageType::RespModelCreateSuccess as u64
-                            {
-                                info!("Resp: model created successfully");
-                            }
-                        } else {
-                            warn!("Resp: model creation failure");
+            MessageType::EvaluateData => {
+                let mdl_name = input_text("Enter loaded model name");
+
+                let inputs: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt(
+                        "Input vectors as json, like [[0.0, 1.0], [1.0, 0.0]], or a dataset name",
+                    )
+                    .interact_text()
+                    .unwrap();
+
+                if !inputs.trim_start().starts_with('[') {
+                    Request::EvaluateData {
+                        mdl_name,
+                        inputs: Vec::new(),
+                        dataset: Some(inputs.trim().to_owned()),
+                    }
+                } else {
+                    match serde_json::from_str(&inputs) {
+                        Ok(inputs) => Request::EvaluateData {
+                            mdl_name,
+                            inputs,
+                            dataset: None,
+                        },
+                        Err(e) => {
+                            error!("Failed to parse input vectors : {}", e);
+                            continue;
                         }
                     }
                 }
             }
-        } else if cmd == MessageType::SaveModelCfg as usize {
-            let mdl_name: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Enter model name")
-                .interact_text()
-                .unwrap();
-
-            let msg = json!({
-                "type": MessageType::SaveModelCfg as usize,
-                "mdl_name": mdl_name,
-            });
-
-            stream.write_all(msg.to_string().as_bytes()).await.unwrap();
-
-            if let Ok(bytes_read) = stream.read(&mut buffer).await {
-                let json_recv: Value =
-                    serde_json::from_slice(&buffer[0..bytes_read]).expect("Failed to parse json");
-
-                if let Value::Object(m) = json_recv {
-                    let msg_resp = m.get("type").unwrap().as_number().unwrap();
-
-                    if msg_resp.as_u64().unwrap() == MessageType::RespModelSaveCfg as u64 {
-                        let msg_status = m.get("status").unwrap().as_u64().unwrap();
+            MessageType::DeleteModel => {
+                let mdl_name = input_text("Enter model name");
+
+                let keep_trash = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Keep a trash copy to restore it later?")
+                    .default(true)
+                    .interact()
+                    .unwrap();
+
+                Request::DeleteModel {
+                    mdl_name,
+                    keep_trash,
+                }
+            }
+            MessageType::RestoreModel => Request::RestoreModel {
+                mdl_name: input_text("Enter deleted model name"),
+            },
+            MessageType::UnloadModel => {
+                let mdl_name = input_text("Enter loaded model name");
+
+                let save = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Save model before unloading?")
+                    .default(true)
+                    .interact()
+                    .unwrap();
+
+                Request::UnloadModel { mdl_name, save }
+            }
+            MessageType::UploadDataset => {
+                let samples = match read_samples() {
+                    Ok(samples) => samples,
+                    Err(e) => {
+                        error!("Failed to read samples : {}", e);
+                        continue;
+                    }
+                };
+
+                let name = input_text("Input dataset name");
+
+                let chunk_size: usize = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Samples per chunk")
+                    .default(1000)
+                    .interact_text()
+                    .unwrap();
+
+                let overwrite = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Overwrite an existing dataset?")
+                    .default(false)
+                    .interact()
+                    .unwrap();
+
+                let chunks: Vec<&[Sample]> = samples.chunks(chunk_size.max(1)).collect();
+
+                for (chunk, chunk_samples) in chunks.iter().enumerate() {
+                    let req = Request::UploadDataset {
+                        name: name.clone(),
+                        chunk,
+                        samples: chunk_samples.to_vec(),
+                        last: chunk + 1 == chunks.len(),
+                        overwrite,
+                    };
+
+                    match send_request(&mut stream, &codec, &mut next_id, req).await {
+                        Some(Response::RespUploadDataset { samples, complete }) => {
+                            info!("Uploaded {} samples", samples);
+
+                            if complete {
+                                info!("Dataset {} stored !", name);
+                            }
+                        }
+                        Some(Response::RespError { code, message, .. }) => {
+                            warn!("Upload failed [code {}] : {}", code, message);
+                            break;
+                        }
+                        Some(other) => {
+                            warn!("Unexpected response : {}", other.msg_type());
+                            break;
+                        }
+                        None => return,
+                    }
+                }
 
-                        if msg_status == 0 {
-                            info!("Model {} cfg saved !", mdl_name);
+                continue;
+            }
+            MessageType::ImportDataset => {
+                let formats = ["CSV", "IDX image and label files"];
+
+                let format = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("File format")
+                    .default(0)
+                    .items(&formats)
+                    .interact()
+                    .unwrap();
+
+                let (files, format) = if format == 0 {
+                    let path = input_text("CSV filepath");
+
+                    let input_columns = input_column_list("Zero-based input columns, like 0,1,2");
+                    let label_columns = input_column_list("Zero-based label columns");
+
+                    let delimiter: char = Input::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Delimiter")
+                        .default(',')
+                        .interact_text()
+                        .unwrap();
+
+                    let has_header = Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Skip header line?")
+                        .default(true)
+                        .interact()
+                        .unwrap();
+
+                    let one_hot = Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt("One-hot encode class labels?")
+                        .default(false)
+                        .interact()
+                        .unwrap();
+
+                    (
+                        vec![("data", path)],
+                        ImportFormat::Csv(CsvOptions {
+                            input_columns,
+                            label_columns,
+                            has_header,
+                            delimiter,
+                            one_hot,
+                            classes: Vec::new(),
+                        }),
+                    )
+                } else {
+                    let images = input_text("IDX images filepath");
+                    let labels = input_text("IDX labels filepath");
+
+                    let normalize = Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Scale byte values to 0..1?")
+                        .default(true)
+                        .interact()
+                        .unwrap();
+
+                    let one_hot = Confirm::with_theme(&ColorfulTheme::default())
+                        .with_prompt("One-hot encode class labels?")
+                        .default(true)
+                        .interact()
+                        .unwrap();
+
+                    (
+                        vec![("images", images), ("labels", labels)],
+                        ImportFormat::Idx(IdxOptions {
+                            normalize,
+                            one_hot,
+                            classes: None,
+                        }),
+                    )
+                };
+
+                let name = input_text("Input dataset name");
+
+                let mdl_name: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Model to check sizes against (empty for none)")
+                    .allow_empty(true)
+                    .interact_text()
+                    .unwrap();
+
+                let overwrite = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Overwrite an existing dataset?")
+                    .default(false)
+                    .interact()
+                    .unwrap();
+
+                let mut uploaded = true;
+
+                for (file, path) in files.iter() {
+                    match upload_file(&mut stream, &codec, &mut next_id, &name, file, path).await {
+                        Ok(size) => info!("Uploaded {} : {} bytes", path, size),
+                        Err(e) => {
+                            error!("Failed to upload {} : {}", path, e);
+                            uploaded = false;
+                            break;
                         }
                     }
                 }
+
+                if !uploaded {
+                    continue;
+                }
+
+                Request::ImportDataset {
+                    name,
+                    format,
+                    mdl_name: Some(mdl_name.trim().to_owned()).filter(|m| !m.is_empty()),
+                    overwrite,
+                }
+            }
+            MessageType::ListDatasets => Request::ListDatasets,
+            MessageType::DeleteDataset => Request::DeleteDataset {
+                name: input_text("Enter dataset name"),
+            },
+            MessageType::JobStatus => Request::JobStatus {
+                job_id: input_job_id(),
+            },
+            MessageType::ListJobs => Request::ListJobs,
+            MessageType::JobResult => Request::JobResult {
+                job_id: input_job_id(),
+            },
+            MessageType::Exit => {
+                info!("Exiting...");
+                break;
+            }
+            other => {
+                warn!("Command {} is not supported yet", other);
+                continue;
+            }
+        };
+
+        let resp = match send_request(&mut stream, &codec, &mut next_id, req).await {
+            Some(resp) => resp,
+            None => break,
+        };
+
+        match resp {
+            Response::RespAvailableModels { available_mdls } => {
+                println!("Available models : ");
+                for (idx, i) in available_mdls.iter().enumerate() {
+                    println!("{} : {}", idx, i);
+                }
+            }
+            Response::RespLoadedModels { loaded_mdls } => {
+                println!("Loaded models : ");
+                for (idx, i) in loaded_mdls.iter().enumerate() {
+                    println!("{} : {}", idx, i);
+                }
+            }
+            Response::RespModelInfoSuccess { mdl_info } => {
+                println!("{}", serde_json::to_string_pretty(&mdl_info).unwrap());
+            }
+            Response::RespModelCreateSuccess => {
+                info!("Resp: model created successfully");
+            }
+            Response::RespLoadModel => {
+                info!("Model loaded !");
+            }
+            Response::RespModelSaveCfg => {
+                info!("Model cfg saved !");
+            }
+            Response::RespTrainModel { result } | Response::RespJobResult { result } => {
+                for (epoch, loss) in result.epoch_loss.iter().enumerate() {
+                    println!("Epoch {} : loss {}", epoch + 1, loss);
+                }
+                info!(
+                    "Trained {} epochs, final loss : {}",
+                    result.epochs, result.final_loss
+                );
+            }
+            Response::RespEvaluateData { outputs } => {
+                for (idx, out) in outputs.iter().enumerate() {
+                    println!("{} : {:?}", idx, out);
+                }
+            }
+            Response::RespDeleteModel => {
+                info!("Model deleted !");
+            }
+            Response::RespRestoreModel => {
+                info!("Model restored !");
             }
-        } else if cmd == MessageType::Exit as usize {
-            info!("Exiting...");
-            break;
+            Response::RespUnloadModel => {
+                info!("Model unloaded !");
+            }
+            Response::RespSaveModelState => {
+                info!("Model state saved !");
+            }
+            Response::RespCheckpoints { checkpoints } => {
+                println!("Checkpoints : ");
+                for c in checkpoints.iter() {
+                    println!("{} | {} bytes | modified {}", c.name, c.size, c.modified);
+                }
+            }
+            Response::RespWelcome { .. } => {
+                warn!("Unexpected welcome message");
+            }
+            Response::RespServerShutdown => {
+                warn!("Server is shutting down");
+            }
+            Response::RespUploadDataset { samples, complete } => {
+                info!("Uploaded {} samples, complete : {}", samples, complete);
+            }
+            Response::RespDatasets { datasets } => {
+                println!("Datasets : ");
+                for d in datasets.iter() {
+                    println!(
+                        "{} | {} samples | {} -> {}",
+                        d.name, d.samples, d.input_size, d.output_size
+                    );
+                }
+            }
+            Response::RespUploadDatasetFile { size } => {
+                info!("Uploaded {} bytes", size);
+            }
+            Response::RespImportDataset { info } => {
+                info!(
+                    "Dataset {} imported : {} samples, {} -> {}",
+                    info.name, info.samples, info.input_size, info.output_size
+                );
+
+                if !info.classes.is_empty() {
+                    info!("Classes : {}", info.classes.join(", "));
+                }
+            }
+            Response::RespDeleteDataset => {
+                info!("Dataset deleted !");
+            }
+            Response::RespSubmitTrainJob { job_id } => {
+                info!("Training job #{} submitted", job_id);
+            }
+            Response::RespJobStatus { job } => {
+                println!("{}", serde_json::to_string_pretty(&job).unwrap());
+            }
+            Response::RespJobs { jobs } => {
+                println!("Jobs : ");
+                for j in jobs.iter() {
+                    println!(
+                        "#{} | {} | {:?} | epoch {}/{} | loss {:?}",
+                        j.id, j.mdl_name, j.state, j.epoch, j.epochs, j.last_loss
+                    );
+                }
+            }
+            Response::RespEvent { event } => {
+                info!("Event : {:?}", event);
+            }
+            Response::RespError {
+                request_type,
+                code,
+                message,
+            } => match request_type {
+                Some(request_type) => {
+                    warn!("{} failed [code {}] : {}", request_type, code, message)
+                }
+                None => warn!("Request failed [code {}] : {}", code, message),
+            },
         }
     }
 }
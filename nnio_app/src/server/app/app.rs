@@ -6,7 +6,13 @@ use std::{
     sync::Arc,
 };
 
+use crate::auth::AuthConfig;
+use crate::dataset_storage::*;
+use crate::job_queue::*;
 use crate::mdl_storage::*;
+use crate::rest::HttpConfig;
+use crate::tls::TlsConfig;
+use crate::unix::UnixSocketConfig;
 
 use directories::*;
 use serde::{Deserialize, Serialize};
@@ -17,13 +23,32 @@ use nevermind_neu::{
     *,
 };
 
-use tokio::sync::Mutex;
-
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Configuration {
+    /// Listen on `net_ip`:`net_port`, can be disabled when `unix_socket` is used
+    pub tcp_enabled: bool,
     pub net_port: u16,
     pub net_ip: String,
+    pub unix_socket: UnixSocketConfig,
+    pub http: HttpConfig,
+    /// Simultaneous client connections, 0 or less for no limit
     pub max_con: i32,
+    pub max_frame_size: usize,
+    /// In-flight requests allowed per connection
+    pub max_pending_requests: usize,
+    /// Seconds a connection may stay without requests before it's closed, 0 to disable
+    pub idle_timeout_secs: u64,
+    /// Seconds allowed to receive a frame once it started arriving, 0 to disable
+    pub read_timeout_secs: u64,
+    /// Sustained requests per second allowed per connection, 0 to disable
+    pub max_requests_per_sec: u32,
+    /// Requests a connection may send at once above `max_requests_per_sec`
+    pub request_burst: u32,
+    /// Seconds to wait on shutdown for in-flight requests, and then again for models to save
+    pub shutdown_timeout_secs: u64,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
 }
 
 impl Configuration {
@@ -37,16 +62,30 @@ impl Configuration {
 impl Default for Configuration {
     fn default() -> Self {
         Self {
+            tcp_enabled: true,
             net_port: 5569,
             net_ip: String::from("127.0.0.1"),
+            unix_socket: UnixSocketConfig::default(),
+            http: HttpConfig::default(),
             max_con: 5,
+            max_frame_size: nnio_common::DEFAULT_MAX_FRAME_SIZE,
+            max_pending_requests: 16,
+            idle_timeout_secs: 600,
+            read_timeout_secs: 30,
+            max_requests_per_sec: 50,
+            request_burst: 100,
+            shutdown_timeout_secs: 30,
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
         }
     }
 }
 
 pub struct App {
     pub cfg: Configuration,
-    mdls: MutexedModelStorage,
+    mdls: SharedModelStorage,
+    dsets: SharedDatasetStorage,
+    jobs: SharedJobQueue,
 }
 
 impl Drop for App {
@@ -59,7 +98,9 @@ impl Default for App {
     fn default() -> Self {
         App {
             cfg: Configuration::default(),
-            mdls: MutexedModelStorage::default(),
+            mdls: SharedModelStorage::default(),
+            dsets: SharedDatasetStorage::default(),
+            jobs: SharedJobQueue::default(),
         }
     }
 }
@@ -90,28 +131,35 @@ impl App {
         return app_dir;
     }
 
-    pub fn from_config_or_default() -> Self {
-        let cfg = Configuration::from_file(&App::get_config_path());
-
-        match cfg {
-            Ok(cfg) => {
-                debug!("Loaded configuration from file!");
-                return App::from_config(cfg);
-            }
-            Err(_) => {
-                debug!("Booting from default configuration!");
-                return App::from_config(Configuration::default());
-            }
+    /// Defaults are used only when there's no config file yet, a file which
+    /// fails to parse is an error so its auth settings are never silently dropped
+    pub fn from_config_or_default() -> Result<Self, Box<dyn Error>> {
+        let cfg_path = App::get_config_path();
+
+        if !cfg_path.exists() {
+            debug!("Booting from default configuration!");
+            return Ok(App::from_config(Configuration::default()));
         }
+
+        let cfg = Configuration::from_file(&cfg_path)
+            .map_err(|e| format!("Failed to load {} : {}", cfg_path.display(), e))?;
+
+        debug!("Loaded configuration from file!");
+
+        Ok(App::from_config(cfg))
     }
 
     pub fn from_config(cfg: Configuration) -> Self {
-        let mut app_dir = App::get_app_dir();
-        app_dir.push("models");
+        let app_dir = App::get_app_dir();
         // Some initialization could be done here
+        let mdls = Arc::new(ModelStorage::from_dir(app_dir.join("models")));
+        let dsets = Arc::new(DatasetStorage::from_dir(app_dir.join("datasets")));
+
         Self {
             cfg,
-            mdls: Arc::new(Mutex::new(ModelStorage::from_dir(app_dir))),
+            jobs: Arc::new(JobQueue::new(mdls.clone(), dsets.clone())),
+            mdls,
+            dsets,
         }
     }
 
@@ -129,7 +177,15 @@ impl App {
         Ok(())
     }
 
-    pub fn clone_model_storage(&self) -> MutexedModelStorage {
+    pub fn clone_model_storage(&self) -> SharedModelStorage {
         self.mdls.clone()
     }
+
+    pub fn clone_dataset_storage(&self) -> SharedDatasetStorage {
+        self.dsets.clone()
+    }
+
+    pub fn clone_job_queue(&self) -> SharedJobQueue {
+        self.jobs.clone()
+    }
 }
@@ -0,0 +1,394 @@
+use std::sync::Arc;
+
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::task;
+
+use nnio_common::*;
+
+/// Each role is allowed everything the previous one is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    ReadOnly,
+    Trainer,
+    Admin,
+}
+
+impl Role {
+    /// Least privileged role allowed to send `msg_type`
+    pub fn required_for(msg_type: MessageType) -> Role {
+        match msg_type {
+            MessageType::GetAvailableModels
+            | MessageType::GetLoadedModels
+            | MessageType::ModelInfo
+            | MessageType::ListCheckpoints
+            | MessageType::ListDatasets
+            | MessageType::EvaluateData
+            | MessageType::JobStatus
+            | MessageType::ListJobs
+            | MessageType::JobResult => Role::ReadOnly,
+            MessageType::LoadModel
+            | MessageType::UnloadModel
+            | MessageType::TrainModel
+            | MessageType::SaveModelState
+            | MessageType::UploadDataset
+            | MessageType::UploadDatasetFile
+            | MessageType::ImportDataset
+            | MessageType::SubmitTrainJob => Role::Trainer,
+            _ => Role::Admin,
+        }
+    }
+
+    /// `required_for` the type of `req`, except that replacing a dataset
+    /// needs an admin as deleting it does
+    pub fn required_for_request(req: &Request) -> Role {
+        match req {
+            Request::UploadDataset {
+                overwrite: true, ..
+            }
+            | Request::ImportDataset {
+                overwrite: true, ..
+            } => Role::Admin,
+            req => Role::required_for(req.msg_type()),
+        }
+    }
+}
+
+/// Pre-shared token, sent by clients as `Credentials::Token`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenEntry {
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+/// `password_hash` is the hex PBKDF2-HMAC-SHA256 of the password with
+/// `salt`, computed with `iterations` rounds
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserEntry {
+    pub name: String,
+    pub password_hash: String,
+    pub salt: String,
+    pub iterations: u32,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub tokens: Vec<TokenEntry>,
+    pub users: Vec<UserEntry>,
+    /// Requests clients may send without credentials
+    pub public_requests: Vec<MessageType>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tokens: Vec::new(),
+            users: Vec::new(),
+            public_requests: vec![
+                MessageType::GetAvailableModels,
+                MessageType::GetLoadedModels,
+                MessageType::ModelInfo,
+            ],
+        }
+    }
+}
+
+/// Client identity established by the handshake
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Token or user name, `None` for anonymous clients
+    pub principal: Option<String>,
+    access: Access,
+}
+
+#[derive(Debug, Clone)]
+enum Access {
+    Role(Role),
+    /// Anonymous clients may only send these
+    Public(Vec<MessageType>),
+}
+
+impl Session {
+    pub fn role(&self) -> Option<Role> {
+        match self.access {
+            Access::Role(role) => Some(role),
+            Access::Public(_) => None,
+        }
+    }
+
+    pub fn check(&self, msg_type: MessageType) -> Result<(), NnioError> {
+        match &self.access {
+            Access::Role(role) if *role >= Role::required_for(msg_type) => Ok(()),
+            Access::Role(_) => Err(NnioError::PermissionDenied(msg_type)),
+            Access::Public(allowed) if allowed.contains(&msg_type) => Ok(()),
+            Access::Public(_) => Err(NnioError::AuthRequired),
+        }
+    }
+
+    /// Like `check`, also looking at the fields of `req`
+    pub fn check_request(&self, req: &Request) -> Result<(), NnioError> {
+        match &self.access {
+            Access::Role(role) if *role < Role::required_for_request(req) => {
+                Err(NnioError::PermissionDenied(req.msg_type()))
+            }
+            _ => self.check(req.msg_type()),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Checks the `Hello` credentials, clients without them get an anonymous
+    /// session limited to `public_requests`. Without authentication every
+    /// client is an admin
+    pub fn authenticate(&self, credentials: Option<&Credentials>) -> Result<Session, NnioError> {
+        if !self.enabled {
+            return Ok(Session {
+                principal: None,
+                access: Access::Role(Role::Admin),
+            });
+        }
+
+        let principal = match credentials {
+            None => {
+                return Ok(Session {
+                    principal: None,
+                    access: Access::Public(self.public_requests.clone()),
+                })
+            }
+            Some(Credentials::Token(token)) => self
+                .tokens
+                .iter()
+                .find(|t| constant_time_eq(t.token.as_bytes(), token.as_bytes()))
+                .map(|t| (t.name.clone(), t.role)),
+            Some(Credentials::Password { user, password }) => {
+                match self.users.iter().find(|u| u.name == *user) {
+                    Some(u) => constant_time_eq(
+                        password_hash(&u.salt, u.iterations, password).as_bytes(),
+                        u.password_hash.to_ascii_lowercase().as_bytes(),
+                    )
+                    .then(|| (u.name.clone(), u.role)),
+                    None => {
+                        // costs as much as a known user, so user names can't
+                        // be found out by timing
+                        if let Some(iterations) = self.users.iter().map(|u| u.iterations).max() {
+                            password_hash(UNKNOWN_USER_SALT, iterations, password);
+                        }
+
+                        None
+                    }
+                }
+            }
+        };
+
+        match principal {
+            Some((principal, role)) => Ok(Session {
+                principal: Some(principal),
+                access: Access::Role(role),
+            }),
+            None => Err(NnioError::AuthFailed),
+        }
+    }
+
+    /// `authenticate` on a blocking thread, password hashing is slow on purpose
+    pub async fn verify(
+        self: Arc<Self>,
+        credentials: Option<Credentials>,
+    ) -> Result<Session, NnioError> {
+        task::spawn_blocking(move || self.authenticate(credentials.as_ref()))
+            .await
+            .unwrap_or(Err(NnioError::AuthFailed))
+    }
+}
+
+/// Hashed with when the user doesn't exist
+const UNKNOWN_USER_SALT: &str = "unknown user";
+
+/// Value for `UserEntry::password_hash`
+pub fn password_hash(salt: &str, iterations: u32, password: &str) -> String {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), iterations, &mut key);
+
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Comparison time doesn't depend on where the inputs differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(role: Role) -> Session {
+        Session {
+            principal: Some(String::from("test")),
+            access: Access::Role(role),
+        }
+    }
+
+    fn upload(overwrite: bool) -> Request {
+        Request::UploadDataset {
+            name: String::from("d"),
+            chunk: 0,
+            samples: Vec::new(),
+            last: true,
+            overwrite,
+        }
+    }
+
+    fn import(overwrite: bool) -> Request {
+        Request::ImportDataset {
+            name: String::from("d"),
+            format: ImportFormat::Idx(IdxOptions::default()),
+            mdl_name: None,
+            overwrite,
+        }
+    }
+
+    fn auth_config() -> AuthConfig {
+        AuthConfig {
+            enabled: true,
+            tokens: vec![TokenEntry {
+                name: String::from("ci"),
+                token: String::from("secret-token"),
+                role: Role::Trainer,
+            }],
+            // RFC 7914 PBKDF2-HMAC-SHA256 vector, first 32 bytes
+            users: vec![UserEntry {
+                name: String::from("alice"),
+                password_hash: String::from(
+                    "55AC046E56E3089FEC1691C22544B605F94185216DDE0465E68B9D57C20DACBC",
+                ),
+                salt: String::from("salt"),
+                iterations: 1,
+                role: Role::Admin,
+            }],
+            ..AuthConfig::default()
+        }
+    }
+
+    fn password(user: &str, password: &str) -> Credentials {
+        Credentials::Password {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    #[test]
+    fn role_boundaries() {
+        let cases = [
+            (
+                Request::RestoreModel {
+                    mdl_name: String::from("m"),
+                },
+                Role::Admin,
+            ),
+            (
+                Request::SaveModelCfg {
+                    mdl_name: String::from("m"),
+                },
+                Role::Admin,
+            ),
+            (
+                Request::DeleteDataset {
+                    name: String::from("d"),
+                },
+                Role::Admin,
+            ),
+            (upload(false), Role::Trainer),
+            (upload(true), Role::Admin),
+            (import(false), Role::Trainer),
+            (import(true), Role::Admin),
+            (Request::ListDatasets, Role::ReadOnly),
+        ];
+
+        for (req, required) in cases {
+            assert_eq!(Role::required_for_request(&req), required);
+
+            for role in [Role::ReadOnly, Role::Trainer, Role::Admin] {
+                match session(role).check_request(&req) {
+                    Ok(()) => assert!(role >= required, "{:?} sent {}", role, req.msg_type()),
+                    Err(NnioError::PermissionDenied(msg_type)) => {
+                        assert!(role < required);
+                        assert_eq!(msg_type, req.msg_type());
+                    }
+                    Err(e) => panic!("unexpected error : {}", e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn anonymous_session_is_limited_to_public_requests() {
+        let auth = auth_config();
+        let anonymous = auth.authenticate(None).unwrap();
+
+        assert!(anonymous.principal.is_none());
+        assert!(anonymous.role().is_none());
+        assert!(anonymous.check(MessageType::GetAvailableModels).is_ok());
+        assert!(anonymous.check_request(&Request::GetLoadedModels).is_ok());
+        assert!(matches!(
+            anonymous.check(MessageType::ListDatasets),
+            Err(NnioError::AuthRequired)
+        ));
+        assert!(matches!(
+            anonymous.check_request(&upload(false)),
+            Err(NnioError::AuthRequired)
+        ));
+    }
+
+    #[test]
+    fn disabled_auth_makes_everyone_admin() {
+        let auth = AuthConfig::default();
+        let session = auth.authenticate(None).unwrap();
+
+        assert_eq!(session.role(), Some(Role::Admin));
+        assert!(session.check_request(&import(true)).is_ok());
+    }
+
+    #[test]
+    fn password_hash_matches_known_vector() {
+        assert_eq!(
+            password_hash("salt", 1, "passwd"),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+    }
+
+    #[test]
+    fn authenticate_checks_credentials() {
+        let auth = auth_config();
+
+        let user = auth
+            .authenticate(Some(&password("alice", "passwd")))
+            .unwrap();
+        assert_eq!(user.principal.as_deref(), Some("alice"));
+        assert_eq!(user.role(), Some(Role::Admin));
+
+        let token = Credentials::Token(String::from("secret-token"));
+        let client = auth.authenticate(Some(&token)).unwrap();
+        assert_eq!(client.principal.as_deref(), Some("ci"));
+        assert_eq!(client.role(), Some(Role::Trainer));
+
+        for credentials in [
+            password("alice", "wrong"),
+            password("bob", "passwd"),
+            Credentials::Token(String::from("secret")),
+        ] {
+            assert!(matches!(
+                auth.authenticate(Some(&credentials)),
+                Err(NnioError::AuthFailed)
+            ));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::BTreeSet;
+
+use nnio_common::*;
+
+/// Guards against a corrupted label turning into a huge one-hot vector
+const MAX_CLASSES: usize = 10_000;
+
+/// Parsed samples with the one-hot classes of their outputs
+pub struct ImportedData {
+    pub samples: Vec<Sample>,
+    pub classes: Vec<String>,
+}
+
+pub fn parse_csv(data: &str, opts: &CsvOptions) -> Result<ImportedData, NnioError> {
+    if opts.input_columns.is_empty() || opts.label_columns.is_empty() {
+        return Err(invalid("Input and label columns must be set".to_owned()));
+    }
+
+    if opts.one_hot && opts.label_columns.len() != 1 {
+        return Err(invalid(
+            "One-hot labels need exactly one label column".to_owned(),
+        ));
+    }
+
+    if !opts.delimiter.is_ascii() {
+        return Err(invalid(format!(
+            "Delimiter {} isn't an ASCII character",
+            opts.delimiter
+        )));
+    }
+
+    // quoted fields may hold the delimiter, quotes and line breaks
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(opts.has_header)
+        .delimiter(opts.delimiter as u8)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+
+    let mut rows: Vec<(u64, csv::StringRecord)> = Vec::new();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| invalid(format!("Bad CSV : {}", e)))?;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+
+        if !record.iter().all(str::is_empty) {
+            rows.push((line, record));
+        }
+    }
+
+    let max_column = opts
+        .input_columns
+        .iter()
+        .chain(opts.label_columns.iter())
+        .max()
+        .copied()
+        .unwrap_or(0);
+
+    if let Some((line, _)) = rows.iter().find(|(_, r)| r.len() <= max_column) {
+        return Err(invalid(format!(
+            "Line {} has no column {}",
+            line, max_column
+        )));
+    }
+
+    let classes = if !opts.one_hot {
+        Vec::new()
+    } else if !opts.classes.is_empty() {
+        opts.classes.clone()
+    } else {
+        let found: BTreeSet<&str> = rows
+            .iter()
+            .map(|(_, r)| &r[opts.label_columns[0]])
+            .collect();
+        let mut classes: Vec<String> = found.into_iter().map(str::to_owned).collect();
+
+        // numeric labels in numeric order, 10 after 9
+        if classes.iter().all(|c| c.parse::<f64>().is_ok()) {
+            classes.sort_by(|a, b| {
+                let (a, b) = (a.parse::<f64>().unwrap(), b.parse::<f64>().unwrap());
+                a.total_cmp(&b)
+            });
+        }
+
+        classes
+    };
+
+    let mut samples = Vec::with_capacity(rows.len());
+
+    for (line, row) in rows.iter() {
+        let input = opts
+            .input_columns
+            .iter()
+            .map(|c| parse_value(&row[*c], *line))
+            .collect::<Result<Vec<f32>, NnioError>>()?;
+
+        let expected = if opts.one_hot {
+            let label = &row[opts.label_columns[0]];
+            let class = classes
+                .iter()
+                .position(|c| c == label)
+                .ok_or_else(|| invalid(format!("Line {} has unknown class {}", line, label)))?;
+
+            one_hot(class, classes.len())
+        } else {
+            opts.label_columns
+                .iter()
+                .map(|c| parse_value(&row[*c], *line))
+                .collect::<Result<Vec<f32>, NnioError>>()?
+        };
+
+        samples.push(Sample { input, expected });
+    }
+
+    Ok(ImportedData { samples, classes })
+}
+
+pub fn parse_idx(
+    images: &[u8],
+    labels: &[u8],
+    opts: &IdxOptions,
+) -> Result<ImportedData, NnioError> {
+    let images = IdxArray::parse(images, "images")?;
+    let labels = IdxArray::parse(labels, "labels")?;
+
+    if images.count() != labels.count() {
+        return Err(NnioError::SizeMismatch {
+            expected: images.count(),
+            got: labels.count(),
+        });
+    }
+
+    let (count, input_size) = (images.count(), images.item_size());
+    let mut inputs = images.values;
+
+    if opts.normalize {
+        inputs.iter_mut().for_each(|v| *v /= 255.0);
+    }
+
+    let (label_values, classes) = if opts.one_hot {
+        if labels.dims.len() != 1 {
+            return Err(invalid("One-hot labels must be 1-dimensional".to_owned()));
+        }
+
+        let count = match opts.classes {
+            Some(count) => count,
+            None => {
+                let max = labels.values.iter().fold(0.0f32, |m, v| m.max(*v));
+
+                // checked before the cast, which saturates
+                if !max.is_finite() || max >= MAX_CLASSES as f32 {
+                    return Err(invalid(format!(
+                        "Label {} is above the limit of {} classes",
+                        max, MAX_CLASSES
+                    )));
+                }
+
+                max as usize + 1
+            }
+        };
+
+        if count > MAX_CLASSES {
+            return Err(invalid(format!("{} classes is too many", count)));
+        }
+
+        let mut values = Vec::with_capacity(labels.count() * count);
+
+        for label in labels.values.iter() {
+            if *label < 0.0 || label.fract() != 0.0 || *label as usize >= count {
+                return Err(invalid(format!(
+                    "Label {} isn't a class below {}",
+                    label, count
+                )));
+            }
+
+            values.extend(one_hot(*label as usize, count));
+        }
+
+        (values, (0..count).map(|c| c.to_string()).collect())
+    } else {
+        (labels.values, Vec::new())
+    };
+
+    let output_size = label_values.len() / count.max(1);
+
+    let samples = inputs
+        .chunks(input_size.max(1))
+        .zip(label_values.chunks(output_size.max(1)))
+        .map(|(input, expected)| Sample {
+            input: input.to_vec(),
+            expected: expected.to_vec(),
+        })
+        .collect();
+
+    Ok(ImportedData { samples, classes })
+}
+
+/// Array of an IDX file, `dims[0]` is the item count
+struct IdxArray {
+    dims: Vec<usize>,
+    values: Vec<f32>,
+}
+
+impl IdxArray {
+    fn parse(data: &[u8], file: &str) -> Result<Self, NnioError> {
+        let bad = |msg: &str| invalid(format!("Bad IDX {} file : {}", file, msg));
+
+        if data.len() < 4 || data[0] != 0 || data[1] != 0 {
+            return Err(bad("wrong magic number"));
+        }
+
+        let value_size = match data[2] {
+            0x08 | 0x09 => 1usize,
+            0x0B => 2,
+            0x0C | 0x0D => 4,
+            0x0E => 8,
+            _ => return Err(bad("unknown data type")),
+        };
+
+        let ndims = data[3] as usize;
+        let header = 4 + ndims * 4;
+
+        if ndims == 0 || data.len() < header {
+            return Err(bad("truncated header"));
+        }
+
+        let dims: Vec<usize> = data[4..header]
+            .chunks(4)
+            .map(|d| u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as usize)
+            .collect();
+
+        let size = dims
+            .iter()
+            .try_fold(value_size, |size, d| size.checked_mul(*d))
+            .and_then(|size| size.checked_add(header));
+
+        if size != Some(data.len()) {
+            return Err(bad("size doesn't match its dimensions"));
+        }
+
+        let values = data[header..]
+            .chunks(value_size)
+            .map(|v| match data[2] {
+                0x08 => v[0] as f32,
+                0x09 => v[0] as i8 as f32,
+                0x0B => i16::from_be_bytes([v[0], v[1]]) as f32,
+                0x0C => i32::from_be_bytes([v[0], v[1], v[2], v[3]]) as f32,
+                0x0D => f32::from_be_bytes([v[0], v[1], v[2], v[3]]),
+                _ => f64::from_be_bytes([v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]]) as f32,
+            })
+            .collect();
+
+        Ok(Self { dims, values })
+    }
+
+    fn count(&self) -> usize {
+        self.dims[0]
+    }
+
+    fn item_size(&self) -> usize {
+        self.dims[1..].iter().product()
+    }
+}
+
+fn one_hot(class: usize, count: usize) -> Vec<f32> {
+    let mut v = vec![0.0; count];
+    v[class] = 1.0;
+    v
+}
+
+fn parse_value(value: &str, line: u64) -> Result<f32, NnioError> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("Line {} has non-numeric value {}", line, value)))
+}
+
+fn invalid(msg: String) -> NnioError {
+    NnioError::InvalidRequest(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_opts(input_columns: Vec<usize>, label_columns: Vec<usize>) -> CsvOptions {
+        CsvOptions {
+            input_columns,
+            label_columns,
+            has_header: false,
+            delimiter: ',',
+            one_hot: false,
+            classes: Vec::new(),
+        }
+    }
+
+    /// IDX file of unsigned bytes, or of big-endian floats with `FLOAT`
+    fn idx(data_type: u8, dims: &[u32], values: &[u8]) -> Vec<u8> {
+        let mut data = vec![0, 0, data_type, dims.len() as u8];
+        dims.iter().for_each(|d| data.extend(d.to_be_bytes()));
+        data.extend(values);
+        data
+    }
+
+    const UBYTE: u8 = 0x08;
+    const FLOAT: u8 = 0x0D;
+
+    fn error_message<T>(res: Result<T, NnioError>) -> String {
+        match res {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn csv_numeric_classes_are_in_numeric_order() {
+        let opts = CsvOptions {
+            one_hot: true,
+            ..csv_opts(vec![0], vec![1])
+        };
+
+        let data = parse_csv("1,10\n2,9\n3,2\n", &opts).unwrap();
+
+        assert_eq!(data.classes, ["2", "9", "10"]);
+        assert_eq!(data.samples[0].expected, [0.0, 0.0, 1.0]);
+        assert_eq!(data.samples[2].expected, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn csv_text_classes_are_sorted() {
+        let opts = CsvOptions {
+            one_hot: true,
+            ..csv_opts(vec![0], vec![1])
+        };
+
+        let data = parse_csv("1,versicolor\n2,setosa\n", &opts).unwrap();
+
+        assert_eq!(data.classes, ["setosa", "versicolor"]);
+        assert_eq!(data.samples[0].expected, [0.0, 1.0]);
+    }
+
+    #[test]
+    fn csv_skips_header_and_blank_lines() {
+        let opts = CsvOptions {
+            has_header: true,
+            ..csv_opts(vec![0, 1], vec![2])
+        };
+
+        let data = parse_csv("a,b,c\n0.5, 1,0\n\n2,3,1\n", &opts).unwrap();
+
+        assert_eq!(data.samples.len(), 2);
+        assert_eq!(data.samples[0].input, [0.5, 1.0]);
+        assert_eq!(data.samples[1].expected, [1.0]);
+    }
+
+    #[test]
+    fn csv_reads_quoted_fields() {
+        let opts = CsvOptions {
+            one_hot: true,
+            ..csv_opts(vec![0], vec![1])
+        };
+
+        let data = parse_csv("1,\"setosa, wild\"\n2,\"say \"\"hi\"\"\"\n", &opts).unwrap();
+
+        assert_eq!(data.classes, ["say \"hi\"", "setosa, wild"]);
+    }
+
+    #[test]
+    fn csv_rejects_out_of_range_column() {
+        let res = parse_csv("1,2\n1,2,3\n", &csv_opts(vec![0, 1], vec![2]));
+
+        assert!(error_message(res).contains("Line 1 has no column 2"));
+    }
+
+    #[test]
+    fn csv_rejects_non_numeric_value() {
+        let res = parse_csv("1,2\nx,2\n", &csv_opts(vec![0], vec![1]));
+
+        assert!(error_message(res).contains("Line 2 has non-numeric value x"));
+    }
+
+    #[test]
+    fn idx_reads_one_hot_samples() {
+        let images = idx(UBYTE, &[2, 2], &[0, 255, 255, 0]);
+        let labels = idx(UBYTE, &[2], &[2, 0]);
+        let opts = IdxOptions {
+            normalize: true,
+            one_hot: true,
+            classes: None,
+        };
+
+        let data = parse_idx(&images, &labels, &opts).unwrap();
+
+        assert_eq!(data.classes, ["0", "1", "2"]);
+        assert_eq!(data.samples[0].input, [0.0, 1.0]);
+        assert_eq!(data.samples[0].expected, [0.0, 0.0, 1.0]);
+        assert_eq!(data.samples[1].expected, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn idx_rejects_truncated_header() {
+        let images = vec![0, 0, UBYTE, 3, 0, 0, 0, 1];
+        let labels = idx(UBYTE, &[1], &[0]);
+
+        let res = parse_idx(&images, &labels, &IdxOptions::default());
+
+        assert!(error_message(res).contains("truncated header"));
+    }
+
+    #[test]
+    fn idx_rejects_size_mismatch() {
+        let images = idx(UBYTE, &[2, 2], &[0, 1, 2]);
+        let labels = idx(UBYTE, &[2], &[0, 1]);
+
+        let res = parse_idx(&images, &labels, &IdxOptions::default());
+
+        assert!(error_message(res).contains("size doesn't match its dimensions"));
+    }
+
+    #[test]
+    fn idx_rejects_count_mismatch() {
+        let images = idx(UBYTE, &[2, 1], &[0, 1]);
+        let labels = idx(UBYTE, &[3], &[0, 1, 2]);
+
+        let res = parse_idx(&images, &labels, &IdxOptions::default());
+
+        assert!(matches!(
+            res,
+            Err(NnioError::SizeMismatch {
+                expected: 2,
+                got: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn idx_rejects_huge_float_labels() {
+        let images = idx(UBYTE, &[2, 1], &[0, 1]);
+        let opts = IdxOptions {
+            one_hot: true,
+            ..IdxOptions::default()
+        };
+
+        for label in [f32::INFINITY, 1e30, MAX_CLASSES as f32] {
+            let values: Vec<u8> = [0.0f32, label]
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect();
+            let labels = idx(FLOAT, &[2], &values);
+
+            let res = parse_idx(&images, &labels, &opts);
+
+            assert!(error_message(res).contains("above the limit"));
+        }
+    }
+}
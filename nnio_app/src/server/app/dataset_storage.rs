@@ -0,0 +1,503 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use nnio_common::*;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, OwnedMutexGuard},
+    task,
+};
+
+use crate::dataset_import::*;
+use crate::mdl_layout::validate_name;
+use crate::mdl_storage::unix_now;
+
+pub type SharedDatasetStorage = Arc<DatasetStorage>;
+
+/// Upload waiting for its next chunk
+struct Upload {
+    next_chunk: usize,
+    overwrite: bool,
+    info: DatasetInfo,
+}
+
+/// Lock of every key in use. The map is only locked to look a key up, so
+/// uploads of different datasets don't wait for each other's file I/O
+struct KeyLocks<K, V>(Mutex<BTreeMap<K, Arc<Mutex<V>>>>);
+
+impl<K: Ord + Clone, V: Default> KeyLocks<K, V> {
+    fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    /// Waits for requests on the same key, a new key starts with the default
+    async fn lock(&self, key: &K) -> OwnedMutexGuard<V> {
+        let lock = self.0.lock().await.entry(key.clone()).or_default().clone();
+
+        lock.lock_owned().await
+    }
+
+    /// Unlocks `key` and forgets its value unless another request waits for it
+    async fn release(&self, key: &K, guard: OwnedMutexGuard<V>) {
+        let mut locks = self.0.lock().await;
+
+        drop(guard);
+
+        if locks
+            .get(key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(key);
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Default> Default for KeyLocks<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Datasets on disk, each one is a directory with `info.yaml` and the
+/// samples as JSON lines. Uploads are written to `.uploads` until their last chunk
+#[derive(Default)]
+pub struct DatasetStorage {
+    dir: PathBuf,
+    uploads: KeyLocks<String, Option<Upload>>,
+    /// Next chunk of every raw file waiting for `import`
+    files: KeyLocks<PathBuf, usize>,
+}
+
+impl DatasetStorage {
+    pub fn from_dir(dir: PathBuf) -> Self {
+        debug!("Creating DatasetStorage from : {}", dir.display());
+
+        let uploads_dir = dir.join(".uploads");
+
+        // uploads don't survive a restart
+        if uploads_dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&uploads_dir) {
+                warn!("Failed to clear unfinished uploads : {}", e);
+            }
+        }
+
+        std::fs::create_dir_all(&uploads_dir)
+            .unwrap_or_else(|e| panic!("Failed to create dir {} : {}", uploads_dir.display(), e));
+
+        Self {
+            dir,
+            uploads: KeyLocks::new(),
+            files: KeyLocks::new(),
+        }
+    }
+
+    fn dataset_dir(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn info_path(&self, name: &str) -> PathBuf {
+        self.dataset_dir(name).join("info.yaml")
+    }
+
+    fn samples_path(&self, name: &str) -> PathBuf {
+        self.dataset_dir(name).join("samples.jsonl")
+    }
+
+    fn upload_path(&self, name: &str) -> PathBuf {
+        self.dir.join(".uploads").join(format!("{}.jsonl", name))
+    }
+
+    fn file_path(&self, name: &str, file: &str) -> PathBuf {
+        self.dir
+            .join(".uploads")
+            .join(format!("{}.file.{}", name, file))
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.info_path(name).exists()
+    }
+
+    /// Appends a chunk to the upload of `name`, returns the samples received
+    /// so far and whether the dataset is stored
+    pub async fn upload_chunk(
+        &self,
+        name: &str,
+        chunk: usize,
+        samples: Vec<Sample>,
+        last: bool,
+        overwrite: bool,
+    ) -> Result<(usize, bool), NnioError> {
+        validate_name("dataset", name)?;
+
+        let key = name.to_owned();
+        let mut upload = self.uploads.lock(&key).await;
+
+        let res = self
+            .append_chunk(name, &mut upload, chunk, samples, last, overwrite)
+            .await;
+
+        // finished and never started uploads
+        if upload.is_none() {
+            self.uploads.release(&key, upload).await;
+        }
+
+        res
+    }
+
+    async fn append_chunk(
+        &self,
+        name: &str,
+        current: &mut Option<Upload>,
+        chunk: usize,
+        samples: Vec<Sample>,
+        last: bool,
+        overwrite: bool,
+    ) -> Result<(usize, bool), NnioError> {
+        if chunk == 0 {
+            if self.exists(name) && !overwrite {
+                return Err(NnioError::DatasetAlreadyExists);
+            }
+
+            let first = samples.first().ok_or_else(|| {
+                NnioError::InvalidRequest("First chunk of a dataset is empty".to_owned())
+            })?;
+
+            *current = Some(Upload {
+                next_chunk: 0,
+                overwrite,
+                info: DatasetInfo {
+                    name: name.to_owned(),
+                    samples: 0,
+                    input_size: first.input.len(),
+                    output_size: first.expected.len(),
+                    created_at: unix_now(),
+                    classes: Vec::new(),
+                },
+            });
+
+            tokio::fs::File::create(self.upload_path(name)).await?;
+        }
+
+        let upload = match current.as_mut() {
+            Some(upload) if upload.next_chunk == chunk => upload,
+            Some(upload) => {
+                return Err(NnioError::InvalidRequest(format!(
+                    "Expected chunk {} of dataset {}, got {}",
+                    upload.next_chunk, name, chunk
+                )))
+            }
+            None => {
+                return Err(NnioError::InvalidRequest(format!(
+                    "No upload of dataset {} in progress, start with chunk 0",
+                    name
+                )))
+            }
+        };
+
+        let mut lines = String::new();
+
+        for s in samples.iter() {
+            check_sample(&upload.info, s)?;
+
+            lines.push_str(
+                &serde_json::to_string(s).map_err(|e| NnioError::CustomError(e.to_string()))?,
+            );
+            lines.push('\n');
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.upload_path(name))
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+
+        upload.next_chunk += 1;
+        upload.info.samples += samples.len();
+
+        let received = upload.info.samples;
+
+        if !last {
+            return Ok((received, false));
+        }
+
+        let upload = current.take().unwrap();
+
+        if self.exists(name) && !upload.overwrite {
+            tokio::fs::remove_file(self.upload_path(name)).await.ok();
+            return Err(NnioError::DatasetAlreadyExists);
+        }
+
+        self.store(name, &self.upload_path(name), &upload.info)
+            .await?;
+
+        info!(
+            "Dataset {} stored : {} samples, {} -> {}",
+            name, upload.info.samples, upload.info.input_size, upload.info.output_size
+        );
+
+        Ok((received, true))
+    }
+
+    /// Moves the samples file `src` into dataset `name`
+    async fn store(&self, name: &str, src: &Path, info: &DatasetInfo) -> Result<(), NnioError> {
+        let data = serde_yaml::to_string(info).map_err(|e| {
+            NnioError::CustomError(format!("Failed to serialize dataset info : {}", e))
+        })?;
+
+        tokio::fs::create_dir_all(self.dataset_dir(name)).await?;
+        tokio::fs::rename(src, self.samples_path(name)).await?;
+        tokio::fs::write(self.info_path(name), data).await?;
+
+        Ok(())
+    }
+
+    /// Appends a base64 chunk to raw file `file` of dataset `name`,
+    /// returns the file size so far
+    pub async fn upload_file(
+        &self,
+        name: &str,
+        file: &str,
+        chunk: usize,
+        data: &str,
+    ) -> Result<u64, NnioError> {
+        validate_name("dataset", name)?;
+        validate_name("file", file)?;
+
+        let bytes = BASE64
+            .decode(data)
+            .map_err(|e| NnioError::InvalidRequest(format!("Bad base64 data : {}", e)))?;
+
+        let path = self.file_path(name, file);
+        let mut next_chunk = self.files.lock(&path).await;
+
+        if chunk != 0 && chunk != *next_chunk {
+            let expected = *next_chunk;
+
+            if expected == 0 {
+                self.files.release(&path, next_chunk).await;
+            }
+
+            return Err(NnioError::InvalidRequest(format!(
+                "Expected chunk {} of file {}, got {}",
+                expected, file, chunk
+            )));
+        }
+
+        let mut f = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(chunk != 0)
+            .truncate(chunk == 0)
+            .open(&path)
+            .await?;
+        f.write_all(&bytes).await?;
+
+        *next_chunk = chunk + 1;
+
+        Ok(f.metadata().await?.len())
+    }
+
+    /// Parses the raw files of dataset `name` and stores the samples. With
+    /// `sizes` set, the input and output sizes of the samples must match them
+    pub async fn import(
+        &self,
+        name: &str,
+        format: ImportFormat,
+        sizes: Option<(usize, usize)>,
+        overwrite: bool,
+    ) -> Result<DatasetInfo, NnioError> {
+        validate_name("dataset", name)?;
+
+        if self.exists(name) && !overwrite {
+            return Err(NnioError::DatasetAlreadyExists);
+        }
+
+        let file_names: &[&str] = match format {
+            ImportFormat::Csv(_) => &["data"],
+            ImportFormat::Idx(_) => &["images", "labels"],
+        };
+
+        let mut files = Vec::new();
+
+        for file in file_names {
+            let path = self.file_path(name, file);
+
+            match tokio::fs::read(&path).await {
+                Ok(data) => files.push(data),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(NnioError::InvalidRequest(format!(
+                        "File {} of dataset {} isn't uploaded",
+                        file, name
+                    )))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // parsing a large file would stall the runtime
+        let imported = task::spawn_blocking(move || match format {
+            ImportFormat::Csv(opts) => {
+                let data = String::from_utf8(files.remove(0))
+                    .map_err(|_| NnioError::InvalidRequest("CSV isn't UTF-8".to_owned()))?;
+                parse_csv(&data, &opts)
+            }
+            ImportFormat::Idx(opts) => parse_idx(&files[0], &files[1], &opts),
+        })
+        .await
+        .map_err(|e| NnioError::CustomError(format!("Dataset import failed : {}", e)))??;
+
+        let first = imported
+            .samples
+            .first()
+            .ok_or_else(|| NnioError::InvalidRequest("Dataset has no samples".to_owned()))?;
+
+        let info = DatasetInfo {
+            name: name.to_owned(),
+            samples: imported.samples.len(),
+            input_size: first.input.len(),
+            output_size: first.expected.len(),
+            created_at: unix_now(),
+            classes: imported.classes,
+        };
+
+        if let Some((input_size, output_size)) = sizes {
+            for (expected, got) in [
+                (input_size, info.input_size),
+                (output_size, info.output_size),
+            ] {
+                if expected != got {
+                    return Err(NnioError::SizeMismatch { expected, got });
+                }
+            }
+        }
+
+        let mut lines = String::new();
+
+        for s in imported.samples.iter() {
+            check_sample(&info, s)?;
+
+            lines.push_str(
+                &serde_json::to_string(s).map_err(|e| NnioError::CustomError(e.to_string()))?,
+            );
+            lines.push('\n');
+        }
+
+        let src = self
+            .dir
+            .join(".uploads")
+            .join(format!("{}.import.jsonl", name));
+        tokio::fs::write(&src, lines).await?;
+
+        self.store(name, &src, &info).await?;
+
+        for file in file_names {
+            let path = self.file_path(name, file);
+            let mut next_chunk = self.files.lock(&path).await;
+
+            tokio::fs::remove_file(&path).await.ok();
+
+            *next_chunk = 0;
+            self.files.release(&path, next_chunk).await;
+        }
+
+        info!(
+            "Dataset {} imported : {} samples, {} -> {}",
+            name, info.samples, info.input_size, info.output_size
+        );
+
+        Ok(info)
+    }
+
+    pub async fn list_datasets(&self) -> Result<Vec<DatasetInfo>, NnioError> {
+        let mut datasets = Vec::new();
+
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let name = match entry.file_name().into_string() {
+                Ok(name) if !name.starts_with('.') => name,
+                _ => continue,
+            };
+
+            match self.dataset_info(&name).await {
+                Ok(info) => datasets.push(info),
+                Err(e) => warn!("Skipping dataset {} : {}", name, e),
+            }
+        }
+
+        datasets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(datasets)
+    }
+
+    pub async fn dataset_info(&self, name: &str) -> Result<DatasetInfo, NnioError> {
+        validate_name("dataset", name)?;
+
+        if !self.exists(name) {
+            return Err(NnioError::DatasetNotExists);
+        }
+
+        read_info(&self.info_path(name)).await
+    }
+
+    /// Reads every sample of the dataset into memory
+    pub async fn load_samples(&self, name: &str) -> Result<Vec<Sample>, NnioError> {
+        let info = self.dataset_info(name).await?;
+
+        let data = tokio::fs::read_to_string(self.samples_path(name)).await?;
+
+        let mut samples = Vec::with_capacity(info.samples);
+
+        for line in data.lines().filter(|l| !l.is_empty()) {
+            samples.push(serde_json::from_str(line).map_err(|e| {
+                NnioError::CustomError(format!("Dataset {} is corrupted : {}", name, e))
+            })?);
+        }
+
+        Ok(samples)
+    }
+
+    pub async fn delete_dataset(&self, name: &str) -> Result<(), NnioError> {
+        validate_name("dataset", name)?;
+
+        if !self.exists(name) {
+            return Err(NnioError::DatasetNotExists);
+        }
+
+        tokio::fs::remove_dir_all(self.dataset_dir(name)).await?;
+
+        info!("Dataset {} deleted", name);
+
+        Ok(())
+    }
+}
+
+async fn read_info(filepath: &Path) -> Result<DatasetInfo, NnioError> {
+    let data = tokio::fs::read_to_string(filepath).await?;
+
+    serde_yaml::from_str(&data)
+        .map_err(|e| NnioError::CustomError(format!("Failed to parse dataset info : {}", e)))
+}
+
+/// Every sample of a dataset has the dimensions of the first one
+fn check_sample(info: &DatasetInfo, sample: &Sample) -> Result<(), NnioError> {
+    if sample.input.len() != info.input_size {
+        return Err(NnioError::SizeMismatch {
+            expected: info.input_size,
+            got: sample.input.len(),
+        });
+    }
+
+    if sample.expected.len() != info.output_size {
+        return Err(NnioError::SizeMismatch {
+            expected: info.output_size,
+            got: sample.expected.len(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use nnio_common::*;
+use tokio::sync::mpsc;
+
+use crate::dataset_storage::*;
+use crate::mdl_storage::*;
+
+pub type SharedJobQueue = Arc<JobQueue>;
+
+/// Finished jobs kept for status queries, the oldest ones are dropped first
+const MAX_FINISHED_JOBS: usize = 1000;
+
+struct Job {
+    info: JobInfo,
+    /// Set once the job is done or failed
+    result: Option<Result<TrainResult, NnioError>>,
+}
+
+/// Job waiting in the queue of its model
+type QueuedJob = (u64, TrainData, TrainParams);
+
+/// Background training jobs, kept in memory only. Jobs of one model run
+/// in submission order, jobs of different models run in parallel
+#[derive(Default)]
+pub struct JobQueue {
+    mdls: SharedModelStorage,
+    dsets: SharedDatasetStorage,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    last_id: AtomicU64,
+    /// Queue of every model that got a job, each one has a worker task
+    queues: Mutex<BTreeMap<String, mpsc::UnboundedSender<QueuedJob>>>,
+}
+
+impl JobQueue {
+    pub fn new(mdls: SharedModelStorage, dsets: SharedDatasetStorage) -> Self {
+        Self {
+            mdls,
+            dsets,
+            jobs: Mutex::new(BTreeMap::new()),
+            last_id: AtomicU64::new(0),
+            queues: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Queues training of `mdl_name` and returns the job id without waiting
+    pub async fn submit(
+        self: &Arc<Self>,
+        mdl_name: String,
+        data: TrainData,
+        params: TrainParams,
+    ) -> Result<u64, NnioError> {
+        if !self.mdls.get_availabel_models().contains(&mdl_name) {
+            return Err(NnioError::ModelNotExists);
+        }
+
+        if let TrainData::Dataset(name) = &data {
+            self.dsets.dataset_info(name).await?;
+        }
+
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let info = JobInfo {
+            id,
+            mdl_name: mdl_name.clone(),
+            state: JobState::Queued,
+            epoch: 0,
+            epochs: params.epochs,
+            last_loss: None,
+            error: None,
+            submitted_at: unix_now(),
+            started_at: None,
+            finished_at: None,
+        };
+
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(id, Job { info, result: None });
+
+        let mut queues = self.queues.lock().unwrap();
+
+        let queue = queues.entry(mdl_name.clone()).or_insert_with(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            tokio::spawn(self.clone().work(mdl_name.clone(), rx));
+            tx
+        });
+
+        // the worker lives as long as the queue itself
+        let _ = queue.send((id, data, params));
+
+        info!("Training job #{} of model {} queued", id, mdl_name);
+
+        Ok(id)
+    }
+
+    /// Runs the jobs of `mdl_name` one by one, not bound to any connection
+    async fn work(self: Arc<Self>, mdl_name: String, mut rx: mpsc::UnboundedReceiver<QueuedJob>) {
+        while let Some((id, data, params)) = rx.recv().await {
+            let res = self.run(id, &mdl_name, data, params).await;
+            self.finish(id, res);
+        }
+    }
+
+    async fn run(
+        &self,
+        id: u64,
+        mdl_name: &String,
+        data: TrainData,
+        params: TrainParams,
+    ) -> Result<TrainResult, NnioError> {
+        let samples = match data {
+            TrainData::Samples(samples) => samples,
+            TrainData::Dataset(name) => self.dsets.load_samples(&name).await?,
+        };
+
+        // the job stays queued behind other runs of the model thread,
+        // such as a `TrainModel` of some client
+        let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+        let train = self
+            .mdls
+            .train_model(mdl_name, samples, params, Some(progress_tx));
+        tokio::pin!(train);
+
+        loop {
+            tokio::select! {
+                // progress is sent before the result, take all of it first
+                biased;
+
+                Some(progress) = progress_rx.recv() => match progress {
+                    TrainProgress::Started => self.update(id, |job| {
+                        job.state = JobState::Running;
+                        job.started_at = Some(unix_now());
+                    }),
+                    TrainProgress::EpochFinished { epoch, loss } => self.update(id, |job| {
+                        job.epoch = epoch;
+                        job.last_loss = Some(loss);
+                    }),
+                },
+                res = &mut train => return res,
+            }
+        }
+    }
+
+    fn finish(&self, id: u64, res: Result<TrainResult, NnioError>) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if let Some(job) = jobs.get_mut(&id) {
+            job.info.finished_at = Some(unix_now());
+
+            match &res {
+                Ok(result) => {
+                    info!("Training job #{} finished", id);
+                    job.info.state = JobState::Done;
+                    job.info.epoch = result.epochs;
+                    job.info.last_loss = Some(result.final_loss);
+                }
+                Err(e) => {
+                    warn!("Training job #{} failed : {}", id, e);
+                    job.info.state = JobState::Failed;
+                    job.info.error = Some(e.to_string());
+                }
+            }
+
+            job.result = Some(res);
+        }
+
+        let finished: Vec<u64> = jobs
+            .iter()
+            .filter(|(_, job)| job.result.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+        {
+            jobs.remove(id);
+        }
+    }
+
+    fn update<F: FnOnce(&mut JobInfo)>(&self, id: u64, f: F) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(&mut job.info);
+        }
+    }
+
+    pub fn job_info(&self, id: u64) -> Result<JobInfo, NnioError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|job| job.info.clone())
+            .ok_or(NnioError::JobNotExists)
+    }
+
+    /// Oldest first
+    pub fn list_jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.info.clone())
+            .collect()
+    }
+
+    pub fn job_result(&self, id: u64) -> Result<TrainResult, NnioError> {
+        match self.jobs.lock().unwrap().get(&id) {
+            Some(Job {
+                result: Some(res), ..
+            }) => res.clone(),
+            Some(_) => Err(NnioError::JobNotFinished),
+            None => Err(NnioError::JobNotExists),
+        }
+    }
+}
@@ -1,20 +1,51 @@
 use nevermind_neu::{models::Sequential, orchestra::Orchestra};
-use serde::{Deserialize, Serialize};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+use std::{
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 use tokio::{
-    io::*,
-    net::{TcpListener, TcpStream},
-    sync::mpsc,
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    sync::{mpsc, watch, Semaphore},
+    task::JoinSet,
+    time::Instant,
 };
-
-use serde_json::{json, Value};
+use tokio_rustls::TlsAcceptor;
 
 use crate::app::*;
 use nnio_common::*;
 
+/// Requests this server build can handle, announced in `RespWelcome`
+pub const SUPPORTED_REQUESTS: &[MessageType] = &[
+    MessageType::GetAvailableModels,
+    MessageType::GetLoadedModels,
+    MessageType::ModelInfo,
+    MessageType::CreateModel,
+    MessageType::LoadModel,
+    MessageType::SaveModelCfg,
+    MessageType::TrainModel,
+    MessageType::EvaluateData,
+    MessageType::DeleteModel,
+    MessageType::RestoreModel,
+    MessageType::UnloadModel,
+    MessageType::SaveModelState,
+    MessageType::ListCheckpoints,
+    MessageType::UploadDataset,
+    MessageType::ListDatasets,
+    MessageType::DeleteDataset,
+    MessageType::UploadDatasetFile,
+    MessageType::ImportDataset,
+    MessageType::SubmitTrainJob,
+    MessageType::JobStatus,
+    MessageType::ListJobs,
+    MessageType::JobResult,
+];
+
 pub struct Listener {
     app: App,
     is_running: AtomicBool,
@@ -33,247 +64,901 @@ impl Listener {
             return;
         }
 
-        let addr = format!("{}:{}", self.app.cfg.net_ip, self.app.cfg.net_port);
+        let http = &self.app.cfg.http;
+
+        // credentials would cross the network in clear text
+        if http.enabled
+            && self.app.cfg.auth.enabled
+            && !self.app.cfg.tls.enabled
+            && !http.is_loopback()
+        {
+            error!(
+                "Refusing to serve HTTP on {} with authentication but without TLS",
+                http.ip
+            );
+            return;
+        }
+
+        let listeners = match Listeners::bind(&self.app.cfg).await {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                error!("Failed to bind : {}", e);
+                return;
+            }
+        };
+
+        let max_con = match self.app.cfg.max_con {
+            n if n > 0 => n as usize,
+            _ => Semaphore::MAX_PERMITS,
+        };
+        let connections = Arc::new(Semaphore::new(max_con));
+
+        let limits = ConnectionLimits::from_config(&self.app.cfg);
+        let auth = Arc::new(self.app.cfg.auth.clone());
+
+        let tls = match self.app.cfg.tls.acceptor() {
+            Ok(tls) => tls,
+            Err(e) => {
+                error!("Failed to set up TLS : {}", e);
+                return;
+            }
+        };
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let mut clients = JoinSet::new();
+
+        if self.app.cfg.http.enabled {
+            let cfg = self.app.cfg.http.clone();
+            let connections = connections.clone();
+            let tls = tls.clone();
+            let auth = auth.clone();
+            let mdls = self.app.clone_model_storage();
+            let dsets = self.app.clone_dataset_storage();
+            let jobs = self.app.clone_job_queue();
+            let stop_rx = stop_rx.clone();
+
+            // joined on shutdown like a client connection
+            clients.spawn(async move {
+                let res = serve_http(
+                    cfg,
+                    limits,
+                    connections,
+                    tls,
+                    auth,
+                    mdls,
+                    dsets,
+                    jobs,
+                    stop_rx,
+                )
+                .await;
 
-        let listener = TcpListener::bind(addr.clone()).await.unwrap();
-        info!("Server is listening on {}", addr);
+                if let Err(e) = res {
+                    error!("{}", e);
+                }
+            });
+        }
 
         loop {
             tokio::select! {
-                vals = listener.accept() => {
-                    let (socket, _) = vals.unwrap();
+                vals = listeners.accept() => {
+                    let (incoming, peer) = match vals {
+                        Ok(vals) => vals,
+                        Err(e) => {
+                            // mostly out of file descriptors, give some time to free them
+                            error!("Failed to accept connection : {}", e);
+                            tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                            continue;
+                        }
+                    };
 
-                    let mdls = self.app.clone_model_storage();
+                    let codec = FrameCodec::new(self.app.cfg.max_frame_size);
+
+                    let con_permit = connections.clone().try_acquire_owned().ok();
+
+                    if con_permit.is_none() {
+                        warn!("Rejecting {} : connection limit reached", peer);
+                    }
 
-                    tokio::spawn(async move {
-                        Listener::handle_client(socket, mdls).await;
+                    let tls = tls.clone();
+                    let auth = auth.clone();
+                    let mdls = self.app.clone_model_storage();
+                    let dsets = self.app.clone_dataset_storage();
+                    let jobs = self.app.clone_job_queue();
+                    let stop_rx = stop_rx.clone();
+
+                    clients.spawn(async move {
+                        // TLS handshake runs here to not hold up the accept loop
+                        let stream: Box<dyn AsyncStream> = match (incoming, tls) {
+                            (Incoming::Tcp(socket), Some(acceptor)) => {
+                                match accept_tls(&acceptor, socket, &peer, limits).await {
+                                    Some(stream) => stream,
+                                    None => return,
+                                }
+                            }
+                            (Incoming::Tcp(socket), None) => Box::new(socket),
+                            // local clients are trusted with plain transport
+                            (Incoming::Unix(socket), _) => Box::new(socket),
+                        };
+
+                        match con_permit {
+                            Some(_permit) => {
+                                Listener::handle_client(stream, codec, limits, auth, mdls, dsets, jobs, stop_rx)
+                                    .await
+                            }
+                            None => Listener::reject_client(stream, codec, limits).await,
+                        }
                     });
                 },
+                // reap finished connections
+                Some(_) = clients.join_next(), if !clients.is_empty() => {},
                 _ = shutdown_rx.recv() => {
                     info!("Graceful shutdown");
                     break;
                 }
             }
         }
+
+        drop(listeners);
+        self.app.cfg.unix_socket.cleanup();
+
+        self.shutdown(stop_tx, clients).await;
     }
 
-    // TODO : splitup each enum entry per func, increase readability
-    async fn handle_client(mut stream: TcpStream, mdls: MutexedModelStorage) {
-        let mut buffer = [0; 8192];
-        while let Ok(bytes_read) = stream.read(&mut buffer).await {
-            if bytes_read == 0 {
-                // Connection closed
-                break;
-            }
+    /// Clients are told the server stops and get `shutdown_timeout_secs` to
+    /// receive answers of in-flight requests, then loaded models are saved
+    async fn shutdown(&self, stop_tx: watch::Sender<bool>, mut clients: JoinSet<()>) {
+        let deadline = Duration::from_secs(self.app.cfg.shutdown_timeout_secs);
 
-            // Process the received data
-            let input_slice = &buffer[..bytes_read];
-            let mut json_msg: Value = serde_json::from_slice(input_slice).unwrap();
+        let _ = stop_tx.send(true);
 
-            if !json_msg.is_object() {
-                continue;
-            }
+        info!("Waiting for {} connections to finish...", clients.len());
 
-            let json_obj = json_msg.as_object_mut().unwrap();
+        let drained = tokio::time::timeout(deadline, async {
+            while clients.join_next().await.is_some() {}
+        })
+        .await;
 
-            if let Some(str_msg_type) = json_obj.get("type").unwrap().as_u64() {
-                let msg_type_res = MessageType::try_from(str_msg_type);
+        if drained.is_err() {
+            warn!(
+                "{} connections didn't finish in time, dropping them",
+                clients.len()
+            );
+            clients.shutdown().await;
+        }
 
-                debug!("Received message : {}", str_msg_type);
+        self.app.clone_model_storage().shutdown(deadline).await;
 
-                if let Ok(msg_type) = msg_type_res {
-                    match msg_type {
-                        MessageType::CreateModel => {
-                            debug!("in create model");
-                            let mut lock = mdls.lock().await;
+        info!("Server stopped");
+    }
 
-                            let mdl_name = json_obj
-                                .remove("name")
-                                .unwrap()
-                                .as_str()
-                                .unwrap()
-                                .to_owned();
+    /// Answers the client `Hello` with `ServerBusy` so it knows why it's dropped
+    async fn reject_client(
+        stream: Box<dyn AsyncStream>,
+        codec: FrameCodec,
+        limits: ConnectionLimits,
+    ) {
+        let (mut reader, mut writer) = tokio::io::split(stream);
 
-                            if mdl_name.is_empty() {
-                                warn!("Received model name is empty, ignoring...");
-                                continue;
-                            }
+        let id = match with_timeout(limits.read_timeout, codec.read_frame(&mut reader)).await {
+            Some(Ok(Some(frame))) => RequestFrame::peek_id(&frame),
+            _ => return,
+        };
 
-                            if let Value::String(net_cfg) = json_obj.remove("net_cfg").unwrap() {
-                                // TODO : handle rewrite flag (third arg)
-                                if let Ok(_) = lock.create_model(net_cfg, mdl_name, false).await {
-                                    let resp = json!({
-                                        "type": MessageType::RespModelCreateSuccess as usize
-                                    });
-
-                                    let resp_ser = serde_json::to_string(&resp).unwrap();
-                                    stream.write_all(resp_ser.as_bytes()).await.unwrap();
-                                } else {
-                                    let resp = json!({
-                                        "type": MessageType::RespModelCreateFailure as usize
-                                    });
-
-                                    let resp_ser = serde_json::to_string(&resp).unwrap();
-                                    stream.write_all(resp_ser.as_bytes()).await.unwrap();
-                                }
-                            }
-                        }
-                        MessageType::DeleteModel => {}
-                        MessageType::GetAvailableModels => {
-                            let lock = mdls.lock().await;
-                            let out = lock.get_availabel_models();
-
-                            let json_mdls =
-                                out.iter().map(|s| json!(s)).collect::<serde_json::Value>();
-
-                            let json_resp = json!({
-                                "type": MessageType::RespAvailableModels as usize,
-                                "available_mdls": json_mdls,
-                            });
-
-                            stream
-                                .write_all(json_resp.to_string().as_bytes())
-                                .await
-                                .unwrap();
-                        }
-                        MessageType::GetLoadedModels => {
-                            let lock = mdls.lock().await;
-                            let out = lock.get_loaded_models();
-
-                            let json_mdls =
-                                out.iter().map(|s| json!(s)).collect::<serde_json::Value>();
-
-                            let json_resp = json!({
-                                "type": MessageType::RespLoadedModels as usize,
-                                "loaded_mdls": json_mdls
-                            });
-
-                            stream
-                                .write_all(json_resp.to_string().as_bytes())
-                                .await
-                                .unwrap();
-                        }
-                        MessageType::UnloadModel => {
-                            let mut lock = mdls.lock().await;
-                        }
-                        MessageType::LoadModel => {
-                            Listener::handle_load_model(&mut stream, mdls.clone(), json_obj).await;
-                        }
-                        MessageType::TrainModel => {}
-                        MessageType::ModelInfo => {
-                            if let Value::String(mdl_name) = json_obj.get("mdl_name").unwrap() {
-                                let mut lock = mdls.lock().await;
-                                if let Some(mdl_info) = lock.get_model_info(mdl_name).await {
-                                    let json_resp = json!({
-                                        "type": MessageType::RespModelInfoSuccess as usize,
-                                        "mdl_info": mdl_info
-                                    });
-
-                                    stream
-                                        .write_all(json_resp.to_string().as_bytes())
-                                        .await
-                                        .unwrap();
-                                } else {
-                                    let json_resp = json!({
-                                        "type": MessageType::RespModelCreateFailure as usize,
-                                    });
-
-                                    stream
-                                        .write_all(json_resp.to_string().as_bytes())
-                                        .await
-                                        .unwrap();
-                                }
-                            }
-                        }
-                        MessageType::SaveModelCfg => {
-                            let mdl_name = json_obj
-                                .get("mdl_name")
-                                .unwrap()
-                                .as_str()
-                                .unwrap()
-                                .to_owned();
-
-                            debug!("Trying to save cfg of model {}", mdl_name);
-
-                            let mut lock = mdls.lock().await;
-
-                            let res = lock.save_model_cfg(&mdl_name).await;
-
-                            match res {
-                                Ok(status) => {
-                                    let json_resp = json!({
-                                        "type": MessageType::RespModelSaveCfg as usize,
-                                        "status": status as usize,
-                                    });
-
-                                    stream
-                                        .write_all(json_resp.to_string().as_bytes())
-                                        .await
-                                        .unwrap();
-                                }
-                                Err(_) => {
-                                    // TODO : handle err type
-                                    let json_resp = json!({
-                                        "type": MessageType::RespModelSaveCfg as usize,
-                                        "status": 0,
-                                    });
-
-                                    stream
-                                        .write_all(json_resp.to_string().as_bytes())
-                                        .await
-                                        .unwrap();
-                                }
+        let resp = Response::error(Some(MessageType::Hello), &NnioError::ServerBusy);
+
+        let _ = codec
+            .write_msg(&mut writer, &ResponseFrame { id, resp })
+            .await;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_client(
+        stream: Box<dyn AsyncStream>,
+        codec: FrameCodec,
+        limits: ConnectionLimits,
+        auth: Arc<AuthConfig>,
+        mdls: SharedModelStorage,
+        dsets: SharedDatasetStorage,
+        jobs: SharedJobQueue,
+        mut stop_rx: watch::Receiver<bool>,
+    ) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        let handshake = Listener::handshake(&mut reader, &mut writer, &codec, &auth);
+
+        let session = match with_timeout(limits.idle_timeout, handshake).await {
+            Some(Ok(session)) => session,
+            Some(Err(e)) => {
+                warn!("Handshake failed : {}", e);
+                return;
+            }
+            None => {
+                warn!("Handshake timed out");
+                return;
+            }
+        };
+
+        let (dispatcher, mut resp_rx) =
+            Dispatcher::new(session, limits.rate_limiter(), limits, mdls, dsets, jobs);
+
+        // responses may come out of order, a single task owns the write half
+        let writer_task = tokio::spawn(async move {
+            while let Some(frame) = resp_rx.recv().await {
+                if let Err(e) = codec.write_msg(&mut writer, &frame).await {
+                    warn!("Failed to write response : {}", e);
+                    break;
+                }
+            }
+        });
+
+        'conn: loop {
+            // idle until the first byte of a frame, `read_timeout` applies from there
+            let arrived = {
+                let first_byte = async { reader.fill_buf().await.map(|b| !b.is_empty()) };
+                tokio::pin!(first_byte);
+
+                loop {
+                    tokio::select! {
+                        res = with_timeout(limits.idle_timeout, &mut first_byte) => match res {
+                            Some(res) => break Some(res),
+                            None if dispatcher.is_busy() => continue,
+                            None => break None,
+                        },
+                        _ = stop_rx.changed() => {
+                            debug!("Server is shutting down, closing connection");
+
+                            let notice = ResponseFrame {
+                                id: UNSOLICITED_ID,
+                                resp: Response::RespServerShutdown,
                             };
+                            dispatcher.send(notice).await;
+
+                            break 'conn;
                         }
-                        MessageType::EvaluateData => {}
-                        _ => {
-                            todo!()
+                    }
+                }
+            };
+
+            let frame = match arrived {
+                Some(Ok(true)) => {
+                    match with_timeout(limits.read_timeout, codec.read_frame(&mut reader)).await {
+                        Some(Ok(Some(frame))) => frame,
+                        Some(Ok(None)) => break,
+                        Some(Err(e)) => {
+                            warn!("Failed to read frame : {}", e);
+                            break;
+                        }
+                        None => {
+                            warn!("Frame read timed out, closing connection");
+                            break;
                         }
                     }
                 }
+                Some(Ok(false)) => break, // Connection closed
+                Some(Err(e)) => {
+                    warn!("Failed to read frame : {}", e);
+                    break;
+                }
+                None => {
+                    debug!("Closing idle connection");
+                    break;
+                }
+            };
+
+            if !dispatcher.dispatch(frame).await {
+                break;
             }
+        }
+
+        drop(dispatcher);
+        writer_task.await.ok();
+    }
+
+    /// Waits for the client `Hello` and answers with `RespWelcome`,
+    /// mismatched, unauthenticated or silent peers get a `RespError` and are dropped
+    async fn handshake<R, W>(
+        reader: &mut R,
+        writer: &mut W,
+        codec: &FrameCodec,
+        auth: &Arc<AuthConfig>,
+    ) -> Result<Session, NnioError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let frame = match codec.read_frame(reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Err(NnioError::HandshakeRequired),
+            Err(e) => return Err(NnioError::InvalidRequest(e.to_string())),
+        };
+
+        let (res, resp) = Listener::greet_frame(&frame, auth).await;
+
+        codec.write_msg(writer, &resp).await?;
+
+        res
+    }
 
-            // Echo the data back to the client
-            // stream.write_all(received_data).unwrap();
+    /// Decodes the first frame of a connection and answers it with `greet`.
+    /// A frame which isn't a request, e.g. from a newer client, is answered
+    /// with a `RespError` too
+    pub(crate) async fn greet_frame(
+        frame: &[u8],
+        auth: &Arc<AuthConfig>,
+    ) -> (Result<Session, NnioError>, ResponseFrame) {
+        match serde_json::from_slice::<RequestFrame>(frame) {
+            Ok(RequestFrame { id, req }) => {
+                let (res, resp) = Listener::greet(req, auth).await;
+                (res, ResponseFrame { id, resp })
+            }
+            Err(e) => {
+                let e = NnioError::InvalidRequest(e.to_string());
+                let resp = ResponseFrame {
+                    id: RequestFrame::peek_id(frame),
+                    resp: Response::error(Some(MessageType::Hello), &e),
+                };
+
+                (Err(e), resp)
+            }
         }
     }
 
-    async fn handle_load_model(
-        stream: &mut TcpStream,
-        mdls: MutexedModelStorage,
-        json_obj: &mut serde_json::Map<String, Value>,
-    ) {
-        let mut lock = mdls.lock().await;
-        let mdl_name = json_obj
-            .get("mdl_name")
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_owned();
-
-        if let Ok(_) = lock.load_model(mdl_name).await {
-            stream
-                .write_all(
-                    json!({
-                        "type": MessageType::RespLoadModel as usize,
-                        "status": 1,
+    /// Answer to the first request of a connection, a session is established
+    /// only if it's a valid `Hello`
+    pub(crate) async fn greet(
+        req: Request,
+        auth: &Arc<AuthConfig>,
+    ) -> (Result<Session, NnioError>, Response) {
+        let res = match req {
+            Request::Hello {
+                protocol_version,
+                client_name,
+                credentials,
+            } => {
+                if protocol_version == PROTOCOL_VERSION {
+                    auth.clone().verify(credentials).await.inspect(|session| {
+                        debug!(
+                            "Client {} connected as {} ({:?})",
+                            client_name,
+                            session.principal.as_deref().unwrap_or("anonymous"),
+                            session.role()
+                        );
                     })
-                    .to_string()
-                    .as_bytes(),
-                )
-                .await;
-        } else {
-            stream
-                .write_all(
-                    json!({
-                        "type": MessageType::RespLoadModel as usize,
-                        "status": 0
+                } else {
+                    Err(NnioError::ProtocolMismatch {
+                        server: PROTOCOL_VERSION,
+                        client: protocol_version,
                     })
-                    .to_string()
-                    .as_bytes(),
-                )
-                .await;
+                }
+            }
+            _ => Err(NnioError::HandshakeRequired),
+        };
+
+        let resp = match &res {
+            Ok(session) => Response::RespWelcome {
+                protocol_version: PROTOCOL_VERSION,
+                server_build: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+                supported: SUPPORTED_REQUESTS.to_vec(),
+                authenticated_as: session.principal.clone(),
+            },
+            Err(e) => Response::error(Some(MessageType::Hello), e),
+        };
+
+        (res, resp)
+    }
+
+    pub(crate) async fn handle_request(
+        req: Request,
+        session: &Session,
+        mdls: SharedModelStorage,
+        dsets: SharedDatasetStorage,
+        jobs: SharedJobQueue,
+    ) -> Response {
+        let msg_type = req.msg_type();
+
+        if !SUPPORTED_REQUESTS.contains(&msg_type) {
+            return Response::error(
+                Some(msg_type),
+                &NnioError::InvalidRequest(format!("{} is not supported", msg_type)),
+            );
         }
+
+        if let Err(e) = session.check_request(&req) {
+            debug!(
+                "Rejecting {} from {} : {}",
+                msg_type,
+                session.principal.as_deref().unwrap_or("anonymous"),
+                e
+            );
+            return Response::error(Some(msg_type), &e);
+        }
+
+        let res = match req {
+            Request::CreateModel {
+                name,
+                net_cfg,
+                overwrite,
+                description,
+                tags,
+            } => {
+                if name.is_empty() {
+                    warn!("Received model name is empty, ignoring...");
+                    return Response::error(
+                        Some(msg_type),
+                        &NnioError::InvalidRequest("Model name is empty".to_owned()),
+                    );
+                }
+
+                mdls.create_model(net_cfg, name, overwrite, description, tags)
+                    .await
+                    .map(|_| Response::RespModelCreateSuccess)
+            }
+            Request::GetAvailableModels => Ok(Response::RespAvailableModels {
+                available_mdls: mdls.get_availabel_models(),
+            }),
+            Request::GetLoadedModels => Ok(Response::RespLoadedModels {
+                loaded_mdls: mdls.get_loaded_models(),
+            }),
+            Request::LoadModel {
+                mdl_name,
+                checkpoint,
+            } => Listener::handle_load_model(mdls, mdl_name, checkpoint).await,
+            Request::ModelInfo { mdl_name } => mdls
+                .get_model_info(&mdl_name)
+                .await
+                .map(|mdl_info| Response::RespModelInfoSuccess { mdl_info }),
+            Request::SaveModelCfg { mdl_name } => {
+                debug!("Trying to save cfg of model {}", mdl_name);
+
+                mdls.save_model_cfg(&mdl_name)
+                    .await
+                    .map(|_| Response::RespModelSaveCfg)
+            }
+            Request::TrainModel {
+                mdl_name,
+                data,
+                params,
+            } => {
+                let samples = match data {
+                    TrainData::Samples(samples) => Ok(samples),
+                    TrainData::Dataset(name) => dsets.load_samples(&name).await,
+                };
+
+                match samples {
+                    Ok(samples) => mdls
+                        .train_model(&mdl_name, samples, params, None)
+                        .await
+                        .map(|result| Response::RespTrainModel { result }),
+                    Err(e) => Err(e),
+                }
+            }
+            Request::EvaluateData {
+                mdl_name,
+                inputs,
+                dataset,
+            } => match Listener::eval_inputs(&dsets, inputs, dataset).await {
+                Ok(inputs) => mdls
+                    .evaluate_data(&mdl_name, inputs)
+                    .await
+                    .map(|outputs| Response::RespEvaluateData { outputs }),
+                Err(e) => Err(e),
+            },
+            Request::UploadDataset {
+                name,
+                chunk,
+                samples,
+                last,
+                overwrite,
+            } => dsets
+                .upload_chunk(&name, chunk, samples, last, overwrite)
+                .await
+                .map(|(samples, complete)| Response::RespUploadDataset { samples, complete }),
+            Request::ListDatasets => dsets
+                .list_datasets()
+                .await
+                .map(|datasets| Response::RespDatasets { datasets }),
+            Request::UploadDatasetFile {
+                name,
+                file,
+                chunk,
+                data,
+            } => dsets
+                .upload_file(&name, &file, chunk, &data)
+                .await
+                .map(|size| Response::RespUploadDatasetFile { size }),
+            Request::ImportDataset {
+                name,
+                format,
+                mdl_name,
+                overwrite,
+            } => match Listener::model_sizes(&mdls, mdl_name).await {
+                Ok(sizes) => dsets
+                    .import(&name, format, sizes, overwrite)
+                    .await
+                    .map(|info| Response::RespImportDataset { info }),
+                Err(e) => Err(e),
+            },
+            Request::DeleteDataset { name } => dsets
+                .delete_dataset(&name)
+                .await
+                .map(|_| Response::RespDeleteDataset),
+            Request::SubmitTrainJob {
+                mdl_name,
+                data,
+                params,
+            } => jobs
+                .submit(mdl_name, data, params)
+                .await
+                .map(|job_id| Response::RespSubmitTrainJob { job_id }),
+            Request::JobStatus { job_id } => jobs
+                .job_info(job_id)
+                .map(|job| Response::RespJobStatus { job }),
+            Request::ListJobs => Ok(Response::RespJobs {
+                jobs: jobs.list_jobs(),
+            }),
+            Request::JobResult { job_id } => jobs
+                .job_result(job_id)
+                .map(|result| Response::RespJobResult { result }),
+            Request::DeleteModel {
+                mdl_name,
+                keep_trash,
+            } => mdls
+                .delete_model(&mdl_name, keep_trash)
+                .await
+                .map(|_| Response::RespDeleteModel),
+            Request::RestoreModel { mdl_name } => mdls
+                .restore_model(&mdl_name)
+                .await
+                .map(|_| Response::RespRestoreModel),
+            Request::UnloadModel { mdl_name, save } => mdls
+                .unload_model(&mdl_name, save)
+                .await
+                .map(|_| Response::RespUnloadModel),
+            Request::SaveModelState {
+                mdl_name,
+                checkpoint,
+            } => mdls
+                .save_model_state(&mdl_name, &checkpoint)
+                .await
+                .map(|_| Response::RespSaveModelState),
+            Request::ListCheckpoints { mdl_name } => mdls
+                .list_checkpoints(&mdl_name)
+                .await
+                .map(|checkpoints| Response::RespCheckpoints { checkpoints }),
+            _ => Err(NnioError::InvalidRequest(format!(
+                "{} is not supported",
+                msg_type
+            ))),
+        };
+
+        match res {
+            Ok(resp) => resp,
+            Err(e) => {
+                debug!("Request {} failed : {}", msg_type, e);
+                Response::error(Some(msg_type), &e)
+            }
+        }
+    }
+
+    /// `inputs` of `EvaluateData`, or the sample inputs of `dataset`
+    pub(crate) async fn eval_inputs(
+        dsets: &DatasetStorage,
+        inputs: Vec<Vec<f32>>,
+        dataset: Option<String>,
+    ) -> Result<Vec<Vec<f32>>, NnioError> {
+        match dataset {
+            Some(_) if !inputs.is_empty() => Err(NnioError::InvalidRequest(
+                "Either inputs or a dataset can be evaluated".to_owned(),
+            )),
+            Some(name) => Ok(dsets
+                .load_samples(&name)
+                .await?
+                .into_iter()
+                .map(|s| s.input)
+                .collect()),
+            None => Ok(inputs),
+        }
+    }
+
+    /// Input and output layer sizes of the model
+    async fn model_sizes(
+        mdls: &ModelStorage,
+        mdl_name: Option<String>,
+    ) -> Result<Option<(usize, usize)>, NnioError> {
+        let mdl_name = match mdl_name {
+            Some(mdl_name) => mdl_name,
+            None => return Ok(None),
+        };
+
+        let layers = mdls.get_model_info(&mdl_name).await?.layers;
+
+        match (layers.first(), layers.last()) {
+            (Some(input), Some(output)) => Ok(Some((input.size, output.size))),
+            _ => Err(NnioError::InvalidRequest(format!(
+                "Model {} has no layers",
+                mdl_name
+            ))),
+        }
+    }
+
+    async fn handle_load_model(
+        mdls: SharedModelStorage,
+        mdl_name: String,
+        checkpoint: Option<String>,
+    ) -> Result<Response, NnioError> {
+        mdls.load_model(mdl_name, checkpoint).await?;
+
+        Ok(Response::RespLoadModel)
+    }
+}
+
+/// Pause after a failed `accept`, so the loop doesn't spin on a persistent error
+pub(crate) const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+enum Incoming {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// TCP and Unix socket listeners, at least one of them is bound
+struct Listeners {
+    tcp: Option<TcpListener>,
+    unix: Option<UnixListener>,
+}
+
+impl Listeners {
+    async fn bind(cfg: &Configuration) -> io::Result<Self> {
+        let tcp = if cfg.tcp_enabled {
+            let addr = format!("{}:{}", cfg.net_ip, cfg.net_port);
+            let listener = TcpListener::bind(&addr).await?;
+            info!("Server is listening on {}", addr);
+            Some(listener)
+        } else {
+            None
+        };
+
+        let unix = cfg.unix_socket.bind()?;
+
+        if unix.is_some() {
+            info!("Server is listening on {}", cfg.unix_socket.path.display());
+        }
+
+        if tcp.is_none() && unix.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Both TCP and Unix socket are disabled",
+            ));
+        }
+
+        Ok(Self { tcp, unix })
+    }
+
+    /// Next connection from either listener with its peer description
+    async fn accept(&self) -> io::Result<(Incoming, String)> {
+        let tcp = async {
+            match &self.tcp {
+                Some(l) => l
+                    .accept()
+                    .await
+                    .map(|(s, peer)| (Incoming::Tcp(s), peer.to_string())),
+                None => std::future::pending().await,
+            }
+        };
+        let unix = async {
+            match &self.unix {
+                Some(l) => l
+                    .accept()
+                    .await
+                    .map(|(s, _)| (Incoming::Unix(s), String::from("unix socket client"))),
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            res = tcp => res,
+            res = unix => res,
+        }
+    }
+}
+
+/// Per-connection limits taken from `Configuration`
+#[derive(Clone, Copy)]
+pub(crate) struct ConnectionLimits {
+    pub(crate) max_frame_size: usize,
+    pub(crate) max_pending: usize,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    /// Requests per second and burst size
+    pub(crate) rate: Option<(u32, u32)>,
+}
+
+impl ConnectionLimits {
+    pub(crate) fn from_config(cfg: &Configuration) -> Self {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+
+        Self {
+            max_frame_size: cfg.max_frame_size,
+            max_pending: cfg.max_pending_requests.max(1),
+            idle_timeout: secs(cfg.idle_timeout_secs),
+            read_timeout: secs(cfg.read_timeout_secs),
+            rate: (cfg.max_requests_per_sec > 0)
+                .then(|| (cfg.max_requests_per_sec, cfg.request_burst.max(1))),
+        }
+    }
+
+    /// Limiter for all requests of one connection, none without `rate`
+    pub(crate) fn rate_limiter(&self) -> Option<Arc<Mutex<RateLimiter>>> {
+        self.rate
+            .map(|(rate, burst)| Arc::new(Mutex::new(RateLimiter::new(rate, burst))))
+    }
+}
+
+/// Runs the requests of one connection after its handshake, the same way
+/// for TCP and WebSocket clients. Responses come out of the receiver given
+/// by `new` in the order they finish
+pub(crate) struct Dispatcher {
+    session: Arc<Session>,
+    mdls: SharedModelStorage,
+    dsets: SharedDatasetStorage,
+    jobs: SharedJobQueue,
+    limiter: Option<Arc<Mutex<RateLimiter>>>,
+    max_pending: usize,
+    pending: Arc<Semaphore>,
+    resp_tx: mpsc::Sender<ResponseFrame>,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(
+        session: Session,
+        limiter: Option<Arc<Mutex<RateLimiter>>>,
+        limits: ConnectionLimits,
+        mdls: SharedModelStorage,
+        dsets: SharedDatasetStorage,
+        jobs: SharedJobQueue,
+    ) -> (Self, mpsc::Receiver<ResponseFrame>) {
+        let (resp_tx, resp_rx) = mpsc::channel(limits.max_pending);
+
+        let dispatcher = Self {
+            session: Arc::new(session),
+            mdls,
+            dsets,
+            jobs,
+            limiter,
+            max_pending: limits.max_pending,
+            pending: Arc::new(Semaphore::new(limits.max_pending)),
+            resp_tx,
+        };
+
+        (dispatcher, resp_rx)
+    }
+
+    /// Queues `frame` for the client, `false` once the connection is closed
+    pub(crate) async fn send(&self, frame: ResponseFrame) -> bool {
+        self.resp_tx.send(frame).await.is_ok()
+    }
+
+    /// Handles the request in `frame` on its own task. Rate limited and
+    /// undecodable frames are answered with a `RespError` carrying their id.
+    /// Returns `false` once the connection is closed
+    pub(crate) async fn dispatch(&self, frame: Vec<u8>) -> bool {
+        if let Some(limiter) = &self.limiter {
+            if !limiter.lock().unwrap().try_acquire() {
+                let resp = ResponseFrame {
+                    id: RequestFrame::peek_id(&frame),
+                    resp: Response::error(None, &NnioError::RateLimited),
+                };
+
+                return self.send(resp).await;
+            }
+        }
+
+        // limits in-flight requests of a single connection
+        let permit = match self.pending.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => return false,
+        };
+
+        let resp_tx = self.resp_tx.clone();
+        let session = self.session.clone();
+        let mdls = self.mdls.clone();
+        let dsets = self.dsets.clone();
+        let jobs = self.jobs.clone();
+
+        tokio::spawn(async move {
+            let resp = match decode_msg::<RequestFrame>(&frame) {
+                Ok(RequestFrame { id, req }) => {
+                    debug!("Received message #{} : {}", id, req.msg_type());
+
+                    ResponseFrame {
+                        id,
+                        resp: Listener::handle_request(req, &session, mdls, dsets, jobs).await,
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to decode request : {}", e);
+
+                    ResponseFrame {
+                        id: RequestFrame::peek_id(&frame),
+                        resp: Response::error(None, &NnioError::InvalidRequest(e.to_string())),
+                    }
+                }
+            };
+
+            // receiver is gone only if the connection is already closed
+            let _ = resp_tx.send(resp).await;
+            drop(permit);
+        });
+
+        true
+    }
+
+    /// A connection waiting for a long request isn't idle
+    pub(crate) fn is_busy(&self) -> bool {
+        self.pending.available_permits() < self.max_pending
+    }
+}
+
+/// Token bucket refilled at `rate` tokens per second up to `burst`
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// TLS handshake of an accepted connection within `read_timeout`, a failed
+/// one is logged and gives `None`
+pub(crate) async fn accept_tls(
+    acceptor: &TlsAcceptor,
+    socket: TcpStream,
+    peer: &str,
+    limits: ConnectionLimits,
+) -> Option<Box<dyn AsyncStream>> {
+    match with_timeout(limits.read_timeout, acceptor.accept(socket)).await {
+        Some(Ok(stream)) => Some(Box::new(stream)),
+        Some(Err(e)) => {
+            warn!("TLS handshake with {} failed : {}", peer, e);
+            None
+        }
+        None => {
+            warn!("TLS handshake with {} timed out", peer);
+            None
+        }
+    }
+}
+
+/// Returns `None` if `fut` didn't finish in time, no limit without `dur`
+pub(crate) async fn with_timeout<F: Future>(dur: Option<Duration>, fut: F) -> Option<F::Output> {
+    match dur {
+        Some(dur) => tokio::time::timeout(dur, fut).await.ok(),
+        None => Some(fut.await),
     }
 }
 
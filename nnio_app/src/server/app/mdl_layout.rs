@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+
+use nnio_common::NnioError;
+
+pub const MODEL_CFG_FILE: &str = "mdl.cfg";
+pub const MODEL_META_FILE: &str = "meta.yaml";
+pub const MODEL_HISTORY_FILE: &str = "history.jsonl";
+pub const MODEL_CHECKPOINTS_DIR: &str = "checkpoints";
+pub const CHECKPOINT_EXT: &str = "state";
+
+/// Config name written by older servers on `SaveModelCfg`
+const LEGACY_CFG_FILE: &str = "net.cfg";
+
+/// On-disk layout of a single model :
+///
+/// ```text
+/// models/<name>/
+///     mdl.cfg            network yaml config
+///     meta.yaml          metadata manifest
+///     history.jsonl      one finished training run per line
+///     checkpoints/
+///         <name>.state   weight checkpoints
+/// ```
+#[derive(Debug, Clone)]
+pub struct ModelDir {
+    root: PathBuf,
+}
+
+impl ModelDir {
+    pub fn new(models_dir: &Path, mdl_name: &str) -> Self {
+        Self {
+            root: models_dir.join(mdl_name),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn cfg(&self) -> PathBuf {
+        self.root.join(MODEL_CFG_FILE)
+    }
+
+    pub fn meta(&self) -> PathBuf {
+        self.root.join(MODEL_META_FILE)
+    }
+
+    pub fn history(&self) -> PathBuf {
+        self.root.join(MODEL_HISTORY_FILE)
+    }
+
+    pub fn checkpoints(&self) -> PathBuf {
+        self.root.join(MODEL_CHECKPOINTS_DIR)
+    }
+
+    pub fn checkpoint(&self, checkpoint: &str) -> Result<PathBuf, NnioError> {
+        validate_name("checkpoint", checkpoint)?;
+
+        Ok(self
+            .checkpoints()
+            .join(format!("{}.{}", checkpoint, CHECKPOINT_EXT)))
+    }
+
+    /// Brings a directory written by an older server to the current layout.
+    ///
+    /// `SaveModelCfg` used to write `net.cfg` which was never loaded again,
+    /// the most recent of `net.cfg` and `mdl.cfg` becomes `mdl.cfg`,
+    /// the other one is kept with an `.old` suffix.
+    pub fn migrate(&self) -> std::io::Result<()> {
+        let legacy_cfg = self.root.join(LEGACY_CFG_FILE);
+        let cfg = self.cfg();
+
+        if legacy_cfg.exists() {
+            let legacy_is_newer = match (legacy_cfg.metadata(), cfg.metadata()) {
+                (Ok(legacy), Ok(current)) => legacy.modified()? > current.modified()?,
+                _ => true, // no mdl.cfg at all
+            };
+
+            if legacy_is_newer {
+                if cfg.exists() {
+                    std::fs::rename(&cfg, self.root.join(format!("{}.old", MODEL_CFG_FILE)))?;
+                }
+
+                std::fs::rename(&legacy_cfg, &cfg)?;
+            } else {
+                std::fs::rename(
+                    &legacy_cfg,
+                    self.root.join(format!("{}.old", LEGACY_CFG_FILE)),
+                )?;
+            }
+
+            info!("Migrated {} config layout", self.root.display());
+        }
+
+        std::fs::create_dir_all(self.checkpoints())
+    }
+}
+
+/// Model and checkpoint names end up in filepaths, keep them plain
+pub fn validate_name(kind: &str, name: &str) -> Result<(), NnioError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(NnioError::InvalidRequest(format!(
+            "Invalid {} name {}",
+            kind, name
+        )))
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use nevermind_neu::{models::Sequential, util::Variant};
+use nnio_common::*;
+
+pub fn read_meta(filepath: &Path) -> Result<ModelMeta, NnioError> {
+    let data = std::fs::read_to_string(filepath)?;
+
+    serde_yaml::from_str(&data)
+        .map_err(|e| NnioError::CustomError(format!("Failed to parse model meta : {}", e)))
+}
+
+pub fn write_meta(filepath: &Path, meta: &ModelMeta) -> Result<(), NnioError> {
+    let data = serde_yaml::to_string(meta)
+        .map_err(|e| NnioError::CustomError(format!("Failed to serialize model meta : {}", e)))?;
+
+    std::fs::write(filepath, data)?;
+
+    Ok(())
+}
+
+/// Per-layer breakdown of a `Sequential` yaml config, read from the layers
+/// of the network built by `Sequential::from_yaml`. Building allocates the
+/// weights, so it belongs on a blocking thread
+pub fn layers_from_cfg(mdl_yaml: &str) -> Result<Vec<LayerInfo>, NnioError> {
+    let seq = Sequential::from_yaml(mdl_yaml)
+        .map_err(|e| NnioError::CustomError(format!("Model yaml parse error : {}", e)))?;
+
+    if seq.layers_count() == 0 {
+        return Err(NnioError::CustomError("Model has no layers".to_owned()));
+    }
+
+    Ok((0..seq.layers_count())
+        .map(|i| {
+            let layer = seq.layer(i);
+
+            LayerInfo {
+                layer_type: layer.layer_type().to_owned(),
+                size: layer.size(),
+                activation: match layer.layer_cfg().get("activation") {
+                    Some(Variant::String(name)) => Some(name.clone()),
+                    _ => None,
+                },
+            }
+        })
+        .collect())
+}
+
+/// `layers_from_cfg` on a blocking thread
+pub async fn read_layers(mdl_yaml: String) -> Result<Vec<LayerInfo>, NnioError> {
+    tokio::task::spawn_blocking(move || layers_from_cfg(&mdl_yaml))
+        .await
+        .map_err(|e| NnioError::CustomError(format!("Failed to read layers : {}", e)))?
+}
+
+/// Weights and biases of fully connected layers following each other
+pub fn param_count(layers: &[LayerInfo]) -> usize {
+    layers.windows(2).map(|w| (w[0].size + 1) * w[1].size).sum()
+}
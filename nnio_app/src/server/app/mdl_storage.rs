@@ -1,51 +1,157 @@
 use std::{
+    any::Any,
     collections::BTreeMap,
-    collections::HashMap,
+    error::Error,
+    panic::{self, AssertUnwindSafe},
     path::PathBuf,
-    sync::Arc,
-    thread::{self, JoinHandle},
-    error::Error
+    sync::{Arc, Mutex, RwLock},
+    thread::{self, JoinHandle, ThreadId},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use nevermind_neu::{dataloader::*, models::*, orchestra::*, util::DataVec};
 use nnio_common::*;
-use serde_json::json;
-use tokio::{sync::mpsc, io::AsyncWriteExt};
-use tokio::{sync::Mutex, task};
+use serde::{Deserialize, Serialize};
+use tokio::task;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc, oneshot},
+};
 
-use crate::app::App;
+use crate::mdl_layout::*;
+use crate::mdl_meta::*;
 
 pub enum ModelMessage {
     // requests
-    Train(Vec<LabeledEntry>),
+    Train(Vec<LabeledEntry>, TrainParams, Option<ProgressSender>),
     Eval(Vec<DataVec>),
-    SaveCfg, // version
-    SaveState(String),
+    SaveCfg(String),   // config filepath
+    SaveState(String), // checkpoint filepath
+    LoadState(String), // checkpoint filepath
     SetBatchSize(usize),
-    Info, // model name
 
     // response
     ModelName(String),
-    TrainResult(HashMap<String, f64>),
+    TrainResult(TrainResult),
     EvalResult(Vec<DataVec>),
-    RespInfo(String),
     RespSave(bool),
+    RespLoad(bool),
+    Error(NnioError),
     Test,
     Stop,
 }
 
+/// Progress of a single training run, reported to whoever started it
+pub enum TrainProgress {
+    /// The model thread picked up the run
+    Started,
+    EpochFinished {
+        epoch: usize,
+        loss: f64,
+    },
+}
+
+pub type ProgressSender = mpsc::UnboundedSender<TrainProgress>;
+
+/// Message to the model thread along with the channel for its reply
+type ModelRequest = (ModelMessage, oneshot::Sender<ModelMessage>);
+
 struct LocalConnection {
-    recver: mpsc::Receiver<ModelMessage>,
-    sender: mpsc::Sender<ModelMessage>,
+    sender: mpsc::Sender<ModelRequest>,
     handle: std::thread::JoinHandle<()>,
 }
 
-//pub type LocalConnection<T> = (mpsc::Receiver<T>, mpsc::Sender<T>);
-pub type MutexedModelStorage = Arc<Mutex<ModelStorage>>;
+#[derive(Default)]
+enum ModelState {
+    #[default]
+    Unloaded,
+    Loaded(LocalConnection),
+    /// Worker panicked, holds the panic message
+    Failed(String),
+}
 
+/// Per-model entry of the storage.
+///
+/// `lifecycle` serializes load, unload and delete of the model, requests to
+/// a loaded model only lock `state` to clone the channel sender, so they run
+/// in parallel with requests to other models.
 #[derive(Default)]
+struct ModelHandle {
+    lifecycle: tokio::sync::Mutex<()>,
+    state: Mutex<ModelState>,
+    /// Guards read-modify-write of `meta.yaml`
+    meta: Mutex<()>,
+}
+
+impl ModelHandle {
+    fn is_loaded(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), ModelState::Loaded(_))
+    }
+
+    fn failure(&self) -> Option<String> {
+        match &*self.state.lock().unwrap() {
+            ModelState::Failed(msg) => Some(msg.clone()),
+            _ => None,
+        }
+    }
+
+    fn take_connection(&self) -> Option<LocalConnection> {
+        let mut state = self.state.lock().unwrap();
+
+        match std::mem::take(&mut *state) {
+            ModelState::Loaded(con) => Some(con),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+
+    /// Marks the model failed if `worker` is still its model thread
+    fn fail(&self, worker: ThreadId, msg: String) -> Option<LocalConnection> {
+        let mut state = self.state.lock().unwrap();
+
+        match &*state {
+            ModelState::Loaded(con) if con.handle.thread().id() == worker => {}
+            _ => return None,
+        }
+
+        match std::mem::replace(&mut *state, ModelState::Failed(msg)) {
+            ModelState::Loaded(con) => Some(con),
+            _ => None,
+        }
+    }
+
+    fn sender(&self) -> Result<mpsc::Sender<ModelRequest>, NnioError> {
+        match &*self.state.lock().unwrap() {
+            ModelState::Loaded(con) => Ok(con.sender.clone()),
+            ModelState::Unloaded => Err(NnioError::ModelNotLoaded),
+            ModelState::Failed(msg) => Err(NnioError::ModelFailed(msg.clone())),
+        }
+    }
+}
+
+pub type SharedModelStorage = Arc<ModelStorage>;
+
+/// Events a slow subscriber may fall behind before it starts losing them
+const EVENTS_CAPACITY: usize = 256;
+
+/// Models available on disk, the map itself is locked only to look up
+/// or add and remove handles, never while waiting on a model
 pub struct ModelStorage {
-    mdls: BTreeMap<String, Option<LocalConnection>>,
+    dir: PathBuf,
+    mdls: RwLock<BTreeMap<String, Arc<ModelHandle>>>,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl Default for ModelStorage {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::default(),
+            mdls: RwLock::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
 }
 
 impl ModelStorage {
@@ -59,7 +165,7 @@ impl ModelStorage {
 
         let mut mdls = BTreeMap::new();
 
-        let mdl_dirs = std::fs::read_dir(dir).expect("Couldn't read directory");
+        let mdl_dirs = std::fs::read_dir(dir.clone()).expect("Couldn't read directory");
 
         for d in mdl_dirs {
             let entry = d.unwrap();
@@ -69,222 +175,1172 @@ impl ModelStorage {
                 match entry.file_name().into_string() {
                     // if EN string
                     Ok(good_string) => {
-                        mdls.insert(good_string, None);
+                        let mdl_dir = ModelDir::new(&dir, &good_string);
+
+                        if let Err(e) = mdl_dir.migrate() {
+                            error!("Failed to migrate model {} : {}", good_string, e);
+                        }
+
+                        if let Err(e) = ModelStorage::ensure_meta(&mdl_dir) {
+                            error!("Failed to create model {} meta : {}", good_string, e);
+                        }
+
+                        mdls.insert(good_string, Arc::new(ModelHandle::default()));
                     }
                     Err(_) => {}
                 }
             }
         }
 
-        Self { mdls }
+        Self {
+            dir,
+            mdls: RwLock::new(mdls),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+
+    /// Receives model events from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: ServerEvent) {
+        // fails only when nobody listens
+        let _ = self.events.send(event);
+    }
+
+    /// Writes `meta.yaml` for models created before manifests existed
+    fn ensure_meta(mdl_dir: &ModelDir) -> Result<(), NnioError> {
+        if mdl_dir.meta().exists() {
+            return Ok(());
+        }
+
+        let layers = layers_from_cfg(&std::fs::read_to_string(mdl_dir.cfg())?)?;
+
+        let created_at = std::fs::metadata(mdl_dir.cfg())
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_else(unix_now);
+
+        write_meta(
+            &mdl_dir.meta(),
+            &ModelMeta {
+                created_at,
+                param_count: param_count(&layers),
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn mdl_dir(&self, mdl_name: &str) -> ModelDir {
+        ModelDir::new(&self.dir, mdl_name)
+    }
+
+    /// Deleted models kept for restoring, next to the models directory
+    fn trash_dir(&self) -> PathBuf {
+        self.dir
+            .parent()
+            .map(|p| p.join("trash"))
+            .unwrap_or_else(|| self.dir.join(".trash"))
+    }
+
+    fn handle(&self, mdl_name: &str) -> Result<Arc<ModelHandle>, NnioError> {
+        match self.mdls.read().unwrap().get(mdl_name) {
+            Some(handle) => Ok(handle.clone()),
+            None => {
+                debug!("Model {} doesn't exist", mdl_name);
+                Err(NnioError::ModelNotExists)
+            }
+        }
+    }
+
+    /// The model could be deleted while we waited for its `lifecycle` lock
+    fn ensure_current(&self, mdl_name: &str, handle: &Arc<ModelHandle>) -> Result<(), NnioError> {
+        match self.mdls.read().unwrap().get(mdl_name) {
+            Some(cur) if Arc::ptr_eq(cur, handle) => Ok(()),
+            _ => Err(NnioError::ModelNotExists),
+        }
     }
 
     pub fn get_availabel_models(&self) -> Vec<String> {
-        self.mdls.keys().cloned().collect()
+        self.mdls.read().unwrap().keys().cloned().collect()
     }
 
     pub fn get_loaded_models(&self) -> Vec<String> {
-        let mut v = Vec::with_capacity(self.mdls.len());
+        self.mdls
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, handle)| handle.is_loaded())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Sends a message to the loaded model thread and waits for its reply,
+    /// no storage lock is held meanwhile
+    async fn send_to_model(
+        &self,
+        mdl_name: &String,
+        msg: ModelMessage,
+    ) -> Result<ModelMessage, NnioError> {
+        let handle = self.handle(mdl_name)?;
+
+        let sender = handle.sender().map_err(|e| {
+            debug!("Model {} is not available : {}", mdl_name, e);
+            e
+        })?;
+
+        // the worker may have died before its supervisor marked it failed
+        request_model(&sender, msg)
+            .await
+            .map_err(|e| match (e, handle.failure()) {
+                (NnioError::ModelCommunication, Some(msg)) => NnioError::ModelFailed(msg),
+                (e, _) => e,
+            })
+    }
+
+    /// Works for unloaded models as well, everything is read from disk
+    pub async fn get_model_info(&self, mdl_name: &String) -> Result<ModelInfo, NnioError> {
+        let handle = self.handle(mdl_name)?;
+        let loaded = handle.is_loaded();
+        let failure = handle.failure();
+
+        let mdl_dir = self.mdl_dir(mdl_name);
+
+        let layers = read_layers(tokio::fs::read_to_string(mdl_dir.cfg()).await?).await?;
 
-        for i in self.mdls.iter() {
-            if i.1.is_some() {
-                // if net was loaded than the channel was created
-                v.push(i.0.clone());
+        let meta = {
+            let _meta_lock = handle.meta.lock().unwrap();
+
+            ModelStorage::ensure_meta(&mdl_dir)?;
+            read_meta(&mdl_dir.meta())?
+        };
+
+        Ok(ModelInfo {
+            name: mdl_name.clone(),
+            loaded,
+            failure,
+            meta,
+            layers,
+        })
+    }
+
+    fn update_meta<F>(&self, mdl_name: &String, f: F) -> Result<(), NnioError>
+    where
+        F: FnOnce(&mut ModelMeta),
+    {
+        let handle = self.handle(mdl_name)?;
+        let _meta_lock = handle.meta.lock().unwrap();
+
+        let mdl_dir = self.mdl_dir(mdl_name);
+
+        ModelStorage::ensure_meta(&mdl_dir)?;
+
+        let mut meta = read_meta(&mdl_dir.meta())?;
+        f(&mut meta);
+
+        write_meta(&mdl_dir.meta(), &meta)
+    }
+
+    pub async fn save_model_cfg(&self, mdl_name: &String) -> Result<(), NnioError> {
+        let filepath = self.mdl_dir(mdl_name).cfg().to_string_lossy().into_owned();
+
+        match self
+            .send_to_model(mdl_name, ModelMessage::SaveCfg(filepath))
+            .await?
+        {
+            ModelMessage::RespSave(true) => Ok(()),
+            ModelMessage::RespSave(false) => Err(NnioError::CustomError(
+                "Failed to write model config".to_owned(),
+            )),
+            _ => Err(NnioError::ModelCommunication),
+        }
+    }
+
+    /// Saves model weights as a named checkpoint under the model directory
+    pub async fn save_model_state(
+        &self,
+        mdl_name: &String,
+        checkpoint: &String,
+    ) -> Result<(), NnioError> {
+        let mdl_dir = self.mdl_dir(mdl_name);
+        let ckpt_path = mdl_dir.checkpoint(checkpoint)?;
+
+        tokio::fs::create_dir_all(mdl_dir.checkpoints()).await?;
+
+        let filepath = ckpt_path.to_string_lossy().into_owned();
+
+        match self
+            .send_to_model(mdl_name, ModelMessage::SaveState(filepath))
+            .await?
+        {
+            ModelMessage::RespSave(true) => {
+                info!("Model {} checkpoint {} saved", mdl_name, checkpoint);
+                Ok(())
             }
+            ModelMessage::RespSave(false) => Err(NnioError::CustomError(
+                "Failed to write model state".to_owned(),
+            )),
+            _ => Err(NnioError::ModelCommunication),
         }
+    }
+
+    pub async fn list_checkpoints(
+        &self,
+        mdl_name: &String,
+    ) -> Result<Vec<CheckpointInfo>, NnioError> {
+        self.handle(mdl_name)?;
+
+        let ckpt_dir = self.mdl_dir(mdl_name).checkpoints();
+
+        let mut out = Vec::new();
 
-        v
+        if !tokio::fs::try_exists(ckpt_dir.clone()).await? {
+            return Ok(out);
+        }
+
+        let mut entries = tokio::fs::read_dir(ckpt_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+
+            if path.extension().and_then(|e| e.to_str()) != Some(CHECKPOINT_EXT) {
+                continue;
+            }
+
+            let meta = entry.metadata().await?;
+
+            out.push(CheckpointInfo {
+                name: path
+                    .file_stem()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size: meta.len(),
+                modified: meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            });
+        }
+
+        out.sort_by(|a, b| a.modified.cmp(&b.modified));
+
+        Ok(out)
     }
 
-    pub async fn get_model_info(&mut self, mdl_name: &String) -> Option<String> {
-        if let Some(mdl_cfg) = self.mdls.get_mut(mdl_name) {
-            // if model config exists ?
-            if let Some(mdl_con) = mdl_cfg {
-                mdl_con.sender.send(ModelMessage::Info).await.unwrap();
-                if let ModelMessage::RespInfo(resp) = mdl_con.recver.recv().await.unwrap() {
-                    return Some(resp);
+    /// Epochs of this run only are sent to `progress`, unlike the
+    /// `EpochFinished` events every subscriber gets
+    pub async fn train_model(
+        &self,
+        mdl_name: &String,
+        samples: Vec<Sample>,
+        params: TrainParams,
+        progress: Option<ProgressSender>,
+    ) -> Result<TrainResult, NnioError> {
+        let data = samples
+            .into_iter()
+            .map(|s| LabeledEntry::new(DataVec::from(s.input), DataVec::from(s.expected)))
+            .collect();
+
+        match self
+            .send_to_model(
+                mdl_name,
+                ModelMessage::Train(data, params.clone(), progress),
+            )
+            .await?
+        {
+            ModelMessage::TrainResult(res) => {
+                let entry = TrainHistoryEntry {
+                    finished_at: unix_now(),
+                    params,
+                    result: res.clone(),
+                };
+
+                if let Err(e) = self.append_history(mdl_name, &entry).await {
+                    warn!("Failed to write {} training history : {}", mdl_name, e);
                 }
+
+                let res_meta = self.update_meta(mdl_name, |meta| {
+                    meta.last_trained_at = Some(entry.finished_at);
+                    meta.total_epochs += res.epochs;
+                    meta.last_loss = Some(res.final_loss);
+                });
+
+                if let Err(e) = res_meta {
+                    warn!("Failed to update {} meta : {}", mdl_name, e);
+                }
+
+                Ok(res)
             }
+            _ => Err(NnioError::ModelCommunication),
         }
+    }
 
-        None
+    async fn append_history(
+        &self,
+        mdl_name: &String,
+        entry: &TrainHistoryEntry,
+    ) -> Result<(), NnioError> {
+        let mut line =
+            serde_json::to_string(entry).map_err(|e| NnioError::CustomError(e.to_string()))?;
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.mdl_dir(mdl_name).history())
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+
+        Ok(())
     }
 
-    pub async fn save_model_cfg(&mut self, mdl_name: &String) -> Result<bool, NnioError> {
-        if let Some(mdl_cfg) = self.mdls.get_mut(mdl_name) {
-            // if model available
-            if let Some(mdl_con) = mdl_cfg {
-                mdl_con.sender.send(ModelMessage::SaveCfg).await.unwrap();
-                if let ModelMessage::RespSave(status) = mdl_con.recver.recv().await.unwrap() {
-                    return Ok(status);
-                } else {
-                    return Err(NnioError::ModelCommunication);
-                }
+    pub async fn evaluate_data(
+        &self,
+        mdl_name: &String,
+        inputs: Vec<Vec<f32>>,
+    ) -> Result<Vec<Vec<f32>>, NnioError> {
+        if inputs.is_empty() {
+            return Err(NnioError::InvalidRequest("No input vectors".to_owned()));
+        }
+
+        let data = inputs.into_iter().map(DataVec::from).collect();
+
+        match self
+            .send_to_model(mdl_name, ModelMessage::Eval(data))
+            .await?
+        {
+            ModelMessage::EvalResult(outputs) => {
+                Ok(outputs.into_iter().map(|o| o.to_vec()).collect())
+            }
+            _ => Err(NnioError::ModelCommunication),
+        }
+    }
+
+    /// Stops the model thread and joins it without blocking the runtime,
+    /// requests queued before the stop are still answered
+    async fn stop_connection(mdl_name: &String, con: LocalConnection) -> Result<(), NnioError> {
+        let (reply_tx, _) = oneshot::channel();
+
+        // thread may be already gone, joining tells why
+        let _ = con.sender.send((ModelMessage::Stop, reply_tx)).await;
+
+        match task::spawn_blocking(move || con.handle.join()).await {
+            Ok(Ok(_)) => {
+                debug!("Model {} thread stopped", mdl_name);
+                Ok(())
+            }
+            _ => {
+                error!("Model {} thread panicked", mdl_name);
+                Err(NnioError::ModelCommunication)
+            }
+        }
+    }
+
+    /// Removes the model from disk, with `keep_trash` the model directory
+    /// is moved to the trash instead and can be brought back by `restore_model`
+    pub async fn delete_model(&self, mdl_name: &String, keep_trash: bool) -> Result<(), NnioError> {
+        let handle = self.handle(mdl_name)?;
+        let _lifecycle = handle.lifecycle.lock().await;
+        self.ensure_current(mdl_name, &handle)?;
+
+        if let Some(con) = handle.take_connection() {
+            info!("Stopping {} model before deletion", mdl_name);
+            if let Err(e) = ModelStorage::stop_connection(mdl_name, con).await {
+                warn!("Model {} wasn't stopped cleanly : {}", mdl_name, e);
+            }
+        }
+
+        let mdl_dir = self.mdl_dir(mdl_name).root().to_path_buf();
+
+        if tokio::fs::try_exists(mdl_dir.clone()).await? {
+            if keep_trash {
+                self.move_to_trash(mdl_name).await?;
             } else {
-                debug!("Trying to save not loaded model");
-                return Err(NnioError::ModelNotLoaded);
+                tokio::fs::remove_dir_all(mdl_dir).await?;
+                info!("Model {} deleted", mdl_name);
+            }
+        }
+
+        self.mdls.write().unwrap().remove(mdl_name);
+
+        Ok(())
+    }
+
+    /// Moves the model directory to the trash, where `restore_model` finds it
+    async fn move_to_trash(&self, mdl_name: &str) -> Result<(), NnioError> {
+        let trash_dir = self.trash_dir();
+        tokio::fs::create_dir_all(trash_dir.clone()).await?;
+
+        // `restore_model` takes the highest stamp, so stamps must not repeat
+        // even for copies made within the same clock tick
+        let mut stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let trash_path = loop {
+            let path = trash_dir.join(format!("{}.{}", mdl_name, stamp));
+
+            if !tokio::fs::try_exists(&path).await? {
+                break path;
             }
-        } else {
-            debug!("Trying to save non-existsing model {}", mdl_name);
+
+            stamp += 1;
+        };
+
+        tokio::fs::rename(self.mdl_dir(mdl_name).root(), &trash_path).await?;
+        info!("Model {} moved to {}", mdl_name, trash_path.display());
+
+        Ok(())
+    }
+
+    /// Brings back the most recent trash copy of a deleted model
+    pub async fn restore_model(&self, mdl_name: &String) -> Result<(), NnioError> {
+        if self.handle(mdl_name).is_ok() {
+            return Err(NnioError::ModelAlreadyExists);
+        }
+
+        let trash_dir = self.trash_dir();
+
+        if !tokio::fs::try_exists(trash_dir.clone()).await? {
             return Err(NnioError::ModelNotExists);
         }
+
+        let prefix = format!("{}.", mdl_name);
+        let mut latest: Option<(u64, PathBuf)> = None;
+
+        let mut entries = tokio::fs::read_dir(trash_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if let Some(ts) = name
+                .strip_prefix(&prefix)
+                .and_then(|ts| ts.parse::<u64>().ok())
+            {
+                if latest.as_ref().map_or(true, |(l, _)| ts > *l) {
+                    latest = Some((ts, entry.path()));
+                }
+            }
+        }
+
+        let (_, trash_path) = latest.ok_or(NnioError::ModelNotExists)?;
+
+        let mdl_dir = self.mdl_dir(mdl_name);
+
+        tokio::fs::rename(trash_path, mdl_dir.root()).await?;
+        mdl_dir.migrate()?;
+        self.mdls
+            .write()
+            .unwrap()
+            .entry(mdl_name.clone())
+            .or_default();
+
+        info!("Model {} restored from trash", mdl_name);
+
+        Ok(())
+    }
+
+    /// Saves every loaded model as `AUTOSAVE_CHECKPOINT` and stops its thread,
+    /// models still busy after `deadline` are left behind
+    pub async fn shutdown(self: &Arc<Self>, deadline: Duration) {
+        let mut tasks = task::JoinSet::new();
+
+        for mdl_name in self.get_loaded_models() {
+            let mdls = self.clone();
+
+            tasks.spawn(async move {
+                if let Err(e) = mdls.unload_model(&mdl_name, true).await {
+                    warn!("Failed to save {} model on shutdown : {}", mdl_name, e);
+                    mdls.unload_model(&mdl_name, false).await.ok();
+                }
+            });
+        }
+
+        let stopped = tokio::time::timeout(deadline, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+
+        if stopped.is_err() {
+            warn!(
+                "{} models are still busy, exiting without saving them",
+                tasks.len()
+            );
+        }
     }
 
-    pub async fn unload_model(&mut self, mdl_name: &String) {
-        if !self.mdls.contains_key(mdl_name) {
+    /// Stops the model thread, the model stays available for a later load
+    pub async fn unload_model(&self, mdl_name: &String, save: bool) -> Result<(), NnioError> {
+        let handle = self.handle(mdl_name).map_err(|e| {
             warn!("Attempt to unload non-existing model : {}", mdl_name);
-            return;
+            e
+        })?;
+        let _lifecycle = handle.lifecycle.lock().await;
+        self.ensure_current(mdl_name, &handle)?;
+
+        if !handle.is_loaded() {
+            warn!("Attempt to unload not loaded model : {}", mdl_name);
+            return Err(NnioError::ModelNotLoaded);
         }
 
-        let con = self.mdls.remove(mdl_name);
+        if save {
+            self.save_model_cfg(mdl_name).await?;
+            self.save_model_state(mdl_name, &AUTOSAVE_CHECKPOINT.to_owned())
+                .await?;
+        }
 
-        match con {
-            Some(con) => {
-                // if model available
-                match con {
-                    Some(con) => {
-                        // if model is loaded (there is a connection)
-                        con.sender.send(ModelMessage::Stop).await.unwrap();
-                        con.handle.join().expect("Failed to join net thread"); // TODO : handle right
-                    }
-                    None => {}
+        if let Some(con) = handle.take_connection() {
+            ModelStorage::stop_connection(mdl_name, con).await?;
+        }
+
+        info!("Model {} unloaded", mdl_name);
+
+        self.emit(ServerEvent::ModelUnloaded {
+            mdl_name: mdl_name.clone(),
+        });
+
+        Ok(())
+    }
+
+    pub async fn load_model(
+        &self,
+        mdl_name: String,
+        checkpoint: Option<String>,
+    ) -> Result<(), NnioError> {
+        let handle = self.handle(&mdl_name).map_err(|e| {
+            debug!("Trying to load non-existsing model {}", mdl_name);
+            e
+        })?;
+        let _lifecycle = handle.lifecycle.lock().await;
+        self.ensure_current(&mdl_name, &handle)?;
+
+        if handle.is_loaded() {
+            warn!("Trying to load a loaded {} model", mdl_name);
+            return Err(NnioError::ModelAlreadyLoaded);
+        }
+
+        let ckpt_path = match checkpoint {
+            Some(ckpt) => {
+                let ckpt_path = self.mdl_dir(&mdl_name).checkpoint(&ckpt)?;
+
+                if !tokio::fs::try_exists(ckpt_path.clone()).await? {
+                    return Err(NnioError::CheckpointNotExists);
                 }
+
+                Some(ckpt_path)
             }
-            None => {}
+            None => None,
         };
-    }
 
-    pub async fn load_model(&mut self, mdl_name: String) -> Result<(), NnioError> {
-        if let Some(con) = self.mdls.get_mut(&mdl_name) { // if model is available
-            if let Some(_) = con {
-                warn!("Trying to load a loaded {} model", mdl_name);
-                return Err(NnioError::ModelAlreadyLoaded);
-            } else {
-                let mut cfgfile = App::get_app_dir();
-                cfgfile.push("models");
-                cfgfile.push(mdl_name.clone());
-                cfgfile.push("mdl.cfg");
-
-                let mdl_yaml = tokio::fs::read_to_string(cfgfile).await.unwrap();
-
-                debug!("Readed {} model yaml", mdl_name);
-
-                let (tx_host, mut rx_mdl) = mpsc::channel(20); // TODO : param must be in configuration
-                let (tx_mdl, rx_host) = mpsc::channel(20);
-
-                info!("Loading {} model...", mdl_name);
-
-                let handle = std::thread::spawn(move || {
-                    debug!("Creating model with yaml cfg : {}", mdl_yaml);
-
-                    let mut orc = Orchestra::new(
-                        Sequential::from_yaml(mdl_yaml.as_str()).expect("Model yaml parse error"),
-                    );
-
-                    orc.name = mdl_name;
-
-                    tx_mdl
-                        .blocking_send(ModelMessage::ModelName(orc.name.clone()))
-                        .unwrap();
-
-                    while let Some(msg) = rx_mdl.blocking_recv() {
-                        match msg {
-                            ModelMessage::Train(_) => {
-                                todo!("Train model")
-                            }
-                            ModelMessage::SetBatchSize(batch_size) => {
-                                // orc.set_train_batch_size(batch_size);
-                            }
-                            ModelMessage::Eval(_) => {
-                                todo!("Eval")
-                            }
-                            ModelMessage::Info => {
-                                let mdl = orc.train_model().unwrap();
-                                let mut out = String::with_capacity(mdl.layers_count() * 2);
-
-                                for l in 0..mdl.layers_count() {
-                                    out += format!("{}-", mdl.layer(l).size()).as_str();
-                                }
-
-                                out.pop();
-
-                                tx_mdl.blocking_send(ModelMessage::RespInfo(out)).unwrap();
-                            }
-                            ModelMessage::Stop => {
-                                debug!("Stopping model {}...", orc.name);
-                                break;
-                            }
-                            ModelMessage::SaveCfg => {
-                                let mut app_path = App::get_app_dir();
-                                app_path.push("models/");
-                                app_path.push(orc.name.clone());
-
-                                std::fs::create_dir_all(app_path.clone())
-                                    .expect("Failed to create model dir");
-
-                                app_path.push("net.cfg");
-
-                                let train_model = orc.train_model().expect("No train model");
-
-                                if let Ok(_) = train_model.to_file(app_path.to_str().unwrap()) {
-                                    tx_mdl.blocking_send(ModelMessage::RespSave(true)).unwrap();
-                                } else {
-                                    tx_mdl.blocking_send(ModelMessage::RespSave(false)).unwrap();
-                                }
-                            }
-                            _ => {
-                                continue;
-                            }
-                        }
-                    }
-                });
+        let mdl_yaml = tokio::fs::read_to_string(self.mdl_dir(&mdl_name).cfg()).await?;
 
-                *con = Some(LocalConnection {
-                    recver: rx_host,
-                    sender: tx_host,
-                    handle,
-                });
+        debug!("Readed {} model yaml", mdl_name);
+
+        let (con, exit_rx) = start_model(&mdl_name, mdl_yaml, self.events.clone()).await?;
+
+        // restore weights before other requests can reach the model
+        if let Some(ckpt_path) = ckpt_path {
+            let filepath = ckpt_path.to_string_lossy().into_owned();
+
+            let res = request_model(&con.sender, ModelMessage::LoadState(filepath)).await;
+
+            if !matches!(res, Ok(ModelMessage::RespLoad(true))) {
+                error!("Failed to restore {} checkpoint", ckpt_path.display());
+
+                ModelStorage::stop_connection(&mdl_name, con).await.ok();
+
+                return Err(res.err().unwrap_or(NnioError::CustomError(
+                    "Failed to restore checkpoint".to_owned(),
+                )));
             }
+
+            info!("Model {} restored from {}", mdl_name, ckpt_path.display());
         }
 
+        let worker = con.handle.thread().id();
+
+        // also clears a previous failure
+        *handle.state.lock().unwrap() = ModelState::Loaded(con);
+
+        self.emit(ServerEvent::ModelLoaded {
+            mdl_name: mdl_name.clone(),
+        });
+
+        tokio::spawn(supervise(
+            mdl_name,
+            handle.clone(),
+            worker,
+            exit_rx,
+            self.events.clone(),
+        ));
+
         Ok(())
     }
 
     pub async fn create_model(
-        &mut self,
+        &self,
         net_cfg: String,
         mdl_name: String,
         overwrite: bool,
+        description: String,
+        tags: Vec<String>,
     ) -> Result<(), NnioError> {
         // write yaml config to folder-file
         // create an entry
-        let mut cfgfile = App::get_app_dir();
-        cfgfile.push("models");
-        cfgfile.push(mdl_name.clone());
+        validate_name("model", &mdl_name)?;
+
+        let layers = read_layers(net_cfg.clone())
+            .await
+            .map_err(|e| NnioError::InvalidRequest(e.to_string()))?;
+
+        // nobody else sees a new handle before it's published, so it's locked first
+        let fresh = Arc::new(ModelHandle::default());
+        let fresh_lifecycle = fresh.lifecycle.try_lock().expect("New model lock is taken");
+
+        let existing = {
+            let mut mdls = self.mdls.write().unwrap();
 
-        if !tokio::fs::try_exists(cfgfile.clone()).await.unwrap() { // model directory exists ?
-            tokio::fs::create_dir_all(cfgfile.clone()).await.unwrap();
+            match mdls.get(&mdl_name) {
+                Some(handle) => Some(handle.clone()),
+                None => {
+                    mdls.insert(mdl_name.clone(), fresh.clone());
+                    None
+                }
+            }
+        };
+
+        let _lifecycle = match &existing {
+            Some(_) if !overwrite => return Err(NnioError::ModelAlreadyExists),
+            Some(handle) => {
+                let lifecycle = handle.lifecycle.lock().await;
+                self.ensure_current(&mdl_name, handle)?;
+
+                if handle.is_loaded() {
+                    warn!("Trying to overwrite loaded model {}", mdl_name);
+                    return Err(NnioError::ModelAlreadyLoaded);
+                }
+
+                // checkpoints and history of the old network don't fit the new one
+                if tokio::fs::try_exists(self.mdl_dir(&mdl_name).root()).await? {
+                    self.move_to_trash(&mdl_name).await?;
+                }
+
+                *handle.state.lock().unwrap() = ModelState::Unloaded;
+
+                lifecycle
+            }
+            None => fresh_lifecycle,
+        };
+
+        let res = self
+            .write_model(&mdl_name, &net_cfg, description, tags, param_count(&layers))
+            .await;
+
+        if res.is_err() && existing.is_none() {
+            self.mdls.write().unwrap().remove(&mdl_name);
         }
 
-        cfgfile.push("mdl.cfg");
+        res
+    }
 
-        if self.mdls.contains_key(&mdl_name) {
-            if !overwrite {
-                return Err(NnioError::ModelAlreadyExists);
-            } else {
-                if tokio::fs::try_exists(cfgfile.clone()).await.unwrap() {
-                    tokio::fs::remove_file(cfgfile.clone()).await.unwrap();
+    async fn write_model(
+        &self,
+        mdl_name: &str,
+        net_cfg: &str,
+        description: String,
+        tags: Vec<String>,
+        param_count: usize,
+    ) -> Result<(), NnioError> {
+        let mdl_dir = self.mdl_dir(mdl_name);
+
+        tokio::fs::create_dir_all(mdl_dir.checkpoints()).await?;
+
+        let mut file = tokio::fs::File::create(mdl_dir.cfg()).await?;
+        file.write_all(net_cfg.as_bytes()).await?;
+
+        write_meta(
+            &mdl_dir.meta(),
+            &ModelMeta {
+                created_at: unix_now(),
+                description,
+                tags,
+                param_count,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+/// Sends a message over the model channel and waits for the reply
+async fn request_model(
+    sender: &mpsc::Sender<ModelRequest>,
+    msg: ModelMessage,
+) -> Result<ModelMessage, NnioError> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+
+    if sender.send((msg, reply_tx)).await.is_err() {
+        return Err(NnioError::ModelCommunication);
+    }
+
+    match reply_rx.await {
+        Ok(ModelMessage::Error(e)) => Err(e),
+        Ok(resp) => Ok(resp),
+        Err(_) => Err(NnioError::ModelCommunication),
+    }
+}
+
+/// How a model thread ended, `Err` holds the panic message
+type WorkerExit = Result<(), String>;
+
+/// Spawns the model thread and waits until the network is built,
+/// the returned receiver reports when the thread ends
+async fn start_model(
+    mdl_name: &String,
+    mdl_yaml: String,
+    events: broadcast::Sender<ServerEvent>,
+) -> Result<(LocalConnection, oneshot::Receiver<WorkerExit>), NnioError> {
+    let (tx_host, mut rx_mdl) = mpsc::channel::<ModelRequest>(20); // TODO : param must be in configuration
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (exit_tx, exit_rx) = oneshot::channel();
+
+    info!("Loading {} model...", mdl_name);
+
+    let thread_mdl_name = mdl_name.clone();
+
+    let handle = std::thread::spawn(move || {
+        let mut pending = None;
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            run_model(
+                thread_mdl_name,
+                mdl_yaml,
+                ready_tx,
+                &events,
+                &mut rx_mdl,
+                &mut pending,
+            )
+        }));
+
+        let exit = match res {
+            Ok(_) => Ok(()),
+            Err(payload) => {
+                let msg = panic_message(payload.as_ref());
+                error!("Model worker panicked : {}", msg);
+
+                // answer the request which crashed the model and the queued ones
+                let err = NnioError::ModelFailed(msg.clone());
+
+                if let Some(reply) = pending.take() {
+                    let _ = reply.send(ModelMessage::Error(err.clone()));
                 }
 
-                // TODO : impl rewrite model (stop -> delete file -> ...)
+                rx_mdl.close();
+                while let Ok((_, reply)) = rx_mdl.try_recv() {
+                    let _ = reply.send(ModelMessage::Error(err.clone()));
+                }
+
+                Err(msg)
+            }
+        };
+
+        let _ = exit_tx.send(exit);
+    });
+
+    match ready_rx.await {
+        Ok(ModelMessage::ModelName(name)) => {
+            info!("Model {} loaded", name);
+
+            Ok((
+                LocalConnection {
+                    sender: tx_host,
+                    handle,
+                },
+                exit_rx,
+            ))
+        }
+        res => {
+            task::spawn_blocking(move || handle.join()).await.ok();
+
+            match (res, exit_rx.await) {
+                (Ok(ModelMessage::Error(e)), _) => Err(e),
+                (_, Ok(Err(msg))) => Err(NnioError::ModelFailed(msg)),
+                _ => Err(NnioError::ModelCommunication),
             }
         }
+    }
+}
 
-        let mut file = tokio::fs::File::create(cfgfile).await.unwrap();
-        file.write(net_cfg.as_bytes()).await.unwrap();
+/// Body of the model thread, `pending` holds the reply channel of the
+/// request being processed so a panic can still be reported to its sender
+fn run_model(
+    mdl_name: String,
+    mdl_yaml: String,
+    ready_tx: oneshot::Sender<ModelMessage>,
+    events: &broadcast::Sender<ServerEvent>,
+    rx_mdl: &mut mpsc::Receiver<ModelRequest>,
+    pending: &mut Option<oneshot::Sender<ModelMessage>>,
+) {
+    debug!("Creating model with yaml cfg : {}", mdl_yaml);
 
-        self.mdls.insert(mdl_name, None);
+    let seq = match Sequential::from_yaml(mdl_yaml.as_str()) {
+        Ok(seq) => seq,
+        Err(e) => {
+            error!("Model yaml parse error : {}", e);
+            let _ = ready_tx.send(ModelMessage::Error(NnioError::CustomError(format!(
+                "Model yaml parse error : {}",
+                e
+            ))));
+            return;
+        }
+    };
 
-        Ok(())
+    let mut orc = Orchestra::new(seq);
+
+    orc.name = mdl_name;
+
+    if ready_tx
+        .send(ModelMessage::ModelName(orc.name.clone()))
+        .is_err()
+    {
+        return;
+    }
+
+    while let Some((msg, reply)) = rx_mdl.blocking_recv() {
+        *pending = Some(reply);
+
+        let resp = match msg {
+            ModelMessage::Train(data, params, progress) => {
+                let report = |p: TrainProgress| {
+                    if let Some(progress) = &progress {
+                        let _ = progress.send(p);
+                    }
+                };
+
+                report(TrainProgress::Started);
+
+                match train_orchestra(&mut orc, data, &params, events, report) {
+                    Ok(res) => ModelMessage::TrainResult(res),
+                    Err(e) => ModelMessage::Error(e),
+                }
+            }
+            ModelMessage::SetBatchSize(batch_size) => {
+                // orc.set_train_batch_size(batch_size);
+                continue;
+            }
+            ModelMessage::Eval(data) => match eval_orchestra(&mut orc, data) {
+                Ok(res) => ModelMessage::EvalResult(res),
+                Err(e) => ModelMessage::Error(e),
+            },
+            ModelMessage::Stop => {
+                debug!("Stopping model {}...", orc.name);
+                break;
+            }
+            ModelMessage::SaveCfg(filepath) => {
+                let train_model = orc.train_model().expect("No train model");
+
+                ModelMessage::RespSave(train_model.to_file(filepath.as_str()).is_ok())
+            }
+            ModelMessage::SaveState(filepath) => {
+                let saved = orc
+                    .train_model()
+                    .map(|mdl| mdl.save_state(filepath.as_str()).is_ok())
+                    .unwrap_or(false);
+
+                ModelMessage::RespSave(saved)
+            }
+            ModelMessage::LoadState(filepath) => {
+                let loaded = orc
+                    .train_model_mut()
+                    .map(|mdl| mdl.load_state(filepath.as_str()).is_ok())
+                    .unwrap_or(false);
+
+                ModelMessage::RespLoad(loaded)
+            }
+            _ => {
+                continue;
+            }
+        };
+
+        // requester may have gone away meanwhile
+        if let Some(reply) = pending.take() {
+            let _ = reply.send(resp);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_owned())
+}
+
+/// Waits for the model thread to end, a panicked worker leaves the model
+/// `Failed` until it's loaded again
+async fn supervise(
+    mdl_name: String,
+    handle: Arc<ModelHandle>,
+    worker: ThreadId,
+    exit_rx: oneshot::Receiver<WorkerExit>,
+    events: broadcast::Sender<ServerEvent>,
+) {
+    match exit_rx.await {
+        Ok(Err(msg)) => {
+            error!("Model {} worker failed : {}", mdl_name, msg);
+
+            let _ = events.send(ServerEvent::ModelFailed {
+                mdl_name: mdl_name.clone(),
+                message: msg.clone(),
+            });
+
+            if let Some(con) = handle.fail(worker, msg) {
+                task::spawn_blocking(move || con.handle.join()).await.ok();
+            }
+        }
+        _ => debug!("Model {} worker exited", mdl_name),
+    }
+}
+
+/// Line of the model training history file
+#[derive(Serialize, Deserialize)]
+struct TrainHistoryEntry {
+    finished_at: u64,
+    params: TrainParams,
+    result: TrainResult,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Checkpoint written when a model is unloaded with saving
+pub const AUTOSAVE_CHECKPOINT: &str = "autosave";
+
+/// Runs on the model thread, trains `orc` and measures loss after every epoch
+fn train_orchestra(
+    orc: &mut Orchestra<Sequential>,
+    data: Vec<LabeledEntry>,
+    params: &TrainParams,
+    events: &broadcast::Sender<ServerEvent>,
+    report: impl Fn(TrainProgress),
+) -> Result<TrainResult, NnioError> {
+    if data.is_empty() {
+        return Err(NnioError::InvalidRequest("No training samples".to_owned()));
+    }
+
+    if params.epochs == 0 || params.batch_size == 0 {
+        return Err(NnioError::InvalidRequest(
+            "Epochs and batch size must be positive".to_owned(),
+        ));
+    }
+
+    {
+        let mdl = orc.train_model().ok_or(NnioError::ModelCommunication)?;
+        let input_size = mdl.layer(0).size();
+        let output_size = mdl.layer(mdl.layers_count() - 1).size();
+
+        for entry in data.iter() {
+            if entry.input.len() != input_size {
+                return Err(NnioError::SizeMismatch {
+                    expected: input_size,
+                    got: entry.input.len(),
+                });
+            }
+
+            if entry.expected.len() != output_size {
+                return Err(NnioError::SizeMismatch {
+                    expected: output_size,
+                    got: entry.expected.len(),
+                });
+            }
+        }
+    }
+
+    let check_set: Vec<(DataVec, DataVec)> = data
+        .iter()
+        .map(|e| (e.input.clone(), e.expected.clone()))
+        .collect();
+
+    let iters_per_epoch = (data.len() + params.batch_size - 1) / params.batch_size;
+
+    orc.set_train_batch_size(params.batch_size);
+
+    // stays set for later runs, as `TrainParams` documents
+    if let Some(lr) = params.learning_rate {
+        orc.set_learn_rate(lr);
+    }
+
+    orc.set_train_dataset(Box::new(SimpleDataLoader::new(data)));
+
+    let mut res = TrainResult::default();
+
+    for epoch in 0..params.epochs {
+        orc.train_for_n_times(iters_per_epoch)
+            .map_err(|e| NnioError::CustomError(format!("Training failed : {}", e)))?;
+
+        let loss = finite_loss(eval_loss(orc, &check_set)?, epoch + 1)?;
+        debug!("Model {} | epoch {} | loss {}", orc.name, epoch + 1, loss);
+
+        let _ = events.send(ServerEvent::EpochFinished {
+            mdl_name: orc.name.clone(),
+            epoch: epoch + 1,
+            epochs: params.epochs,
+            loss,
+        });
+
+        report(TrainProgress::EpochFinished {
+            epoch: epoch + 1,
+            loss,
+        });
+
+        res.epoch_loss.push(loss);
+    }
+
+    res.epochs = params.epochs;
+    res.final_loss = *res.epoch_loss.last().unwrap_or(&0.0);
+
+    Ok(res)
+}
+
+/// Runs on the model thread, feeds every input through `orc`
+fn eval_orchestra(
+    orc: &mut Orchestra<Sequential>,
+    data: Vec<DataVec>,
+) -> Result<Vec<DataVec>, NnioError> {
+    let input_size = orc
+        .train_model()
+        .ok_or(NnioError::ModelCommunication)?
+        .layer(0)
+        .size();
+
+    if let Some(bad) = data.iter().find(|d| d.len() != input_size) {
+        return Err(NnioError::SizeMismatch {
+            expected: input_size,
+            got: bad.len(),
+        });
+    }
+
+    data.into_iter()
+        .enumerate()
+        .map(|(n, d)| {
+            orc.eval_one(d)
+                .map_err(|e| NnioError::CustomError(format!("Evaluation failed : {}", e)))
+                .and_then(|out| finite_output(out, n))
+        })
+        .collect()
+}
+
+/// JSON has no NaN or infinity, serde_json writes them as `null` and
+/// clients then fail to decode the whole response
+fn finite_loss(loss: f64, epoch: usize) -> Result<f64, NnioError> {
+    if loss.is_finite() {
+        Ok(loss)
+    } else {
+        Err(NnioError::CustomError(format!(
+            "Training diverged, loss is {} after epoch {}",
+            loss, epoch
+        )))
+    }
+}
+
+/// Same as `finite_loss` for the output of input `n`
+fn finite_output(out: DataVec, n: usize) -> Result<DataVec, NnioError> {
+    if out.iter().all(|v| v.is_finite()) {
+        Ok(out)
+    } else {
+        Err(NnioError::CustomError(format!(
+            "Output for input {} isn't a finite number",
+            n
+        )))
+    }
+}
+
+/// Mean squared error of the model output over `check_set`
+fn eval_loss(
+    orc: &mut Orchestra<Sequential>,
+    check_set: &[(DataVec, DataVec)],
+) -> Result<f64, NnioError> {
+    let mut err_sum = 0.0;
+
+    for (input, expected) in check_set.iter() {
+        let out = orc
+            .eval_one(input.clone())
+            .map_err(|e| NnioError::CustomError(format!("Evaluation failed : {}", e)))?;
+
+        err_sum += out
+            .iter()
+            .zip(expected.iter())
+            .map(|(o, e)| ((o - e) as f64).powi(2))
+            .sum::<f64>()
+            / expected.len() as f64;
+    }
+
+    Ok(err_sum / check_set.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_loss_is_an_error() {
+        assert_eq!(finite_loss(0.25, 1).unwrap(), 0.25);
+
+        for loss in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(finite_loss(loss, 3).is_err());
+        }
+    }
+
+    #[test]
+    fn non_finite_output_is_an_error() {
+        assert!(finite_output(DataVec::from(vec![0.5, -1.0]), 0).is_ok());
+        assert!(finite_output(DataVec::from(vec![0.5, f32::NAN]), 0).is_err());
+        assert!(finite_output(DataVec::from(vec![f32::INFINITY]), 0).is_err());
+    }
+
+    #[test]
+    fn train_result_round_trips() {
+        let loss = finite_loss(0.125, 1).unwrap();
+        let frame = ResponseFrame {
+            id: 7,
+            resp: Response::RespTrainModel {
+                result: TrainResult {
+                    epochs: 1,
+                    epoch_loss: vec![loss],
+                    final_loss: loss,
+                },
+            },
+        };
+
+        let json = serde_json::to_string(&frame).unwrap();
+
+        match serde_json::from_str::<ResponseFrame>(&json).unwrap().resp {
+            Response::RespTrainModel { result } => {
+                assert_eq!(result.epoch_loss, [0.125]);
+                assert_eq!(result.final_loss, 0.125);
+            }
+            _ => panic!("unexpected response"),
+        }
+    }
+
+    #[test]
+    fn nan_loss_does_not_round_trip() {
+        let result = TrainResult {
+            epochs: 1,
+            epoch_loss: vec![f64::NAN],
+            final_loss: f64::NAN,
+        };
+
+        let json = serde_json::to_string(&result).unwrap();
+
+        assert!(serde_json::from_str::<TrainResult>(&json).is_err());
     }
 }
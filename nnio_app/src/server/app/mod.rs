@@ -1,7 +1,25 @@
 pub mod app;
+pub mod auth;
+pub mod dataset_import;
+pub mod dataset_storage;
+pub mod job_queue;
 pub mod listener;
+pub mod mdl_layout;
+pub mod mdl_meta;
 pub mod mdl_storage;
+pub mod rest;
+pub mod tls;
+pub mod unix;
 
 pub use app::*;
+pub use auth::*;
+pub use dataset_import::*;
+pub use dataset_storage::*;
+pub use job_queue::*;
 pub use listener::*;
-pub use mdl_storage::*;
\ No newline at end of file
+pub use mdl_layout::*;
+pub use mdl_meta::*;
+pub use mdl_storage::*;
+pub use rest::*;
+pub use tls::*;
+pub use unix::*;
\ No newline at end of file
@@ -0,0 +1,752 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    extract::{
+        connect_info::Connected,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, FromRequestParts, Path, Query, State,
+    },
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization, HeaderMapExt,
+    },
+    http::{request::Parts, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response as HttpResponse},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{Sink, SinkExt, StreamExt};
+use hyper::server::accept::Accept;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+    sync::{broadcast, mpsc, watch, OwnedSemaphorePermit, Semaphore},
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::app::*;
+use nnio_common::*;
+
+/// REST gateway for clients which can't speak the framed protocol
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub ip: String,
+    pub port: u16,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ip: String::from("127.0.0.1"),
+            port: 5570,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Only clients of this host can reach the gateway
+    pub fn is_loopback(&self) -> bool {
+        self.ip
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Clone)]
+struct Gateway {
+    auth: Arc<AuthConfig>,
+    mdls: SharedModelStorage,
+    dsets: SharedDatasetStorage,
+    jobs: SharedJobQueue,
+    /// Applied to WebSockets as to TCP connections
+    limits: ConnectionLimits,
+    stop_rx: watch::Receiver<bool>,
+}
+
+/// Serves the REST routes and `/ws` until `stop_rx` turns true. Each HTTP
+/// connection takes a permit of `connections` and has its own rate limit
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn serve_http(
+    cfg: HttpConfig,
+    limits: ConnectionLimits,
+    connections: Arc<Semaphore>,
+    tls: Option<TlsAcceptor>,
+    auth: Arc<AuthConfig>,
+    mdls: SharedModelStorage,
+    dsets: SharedDatasetStorage,
+    jobs: SharedJobQueue,
+    mut stop_rx: watch::Receiver<bool>,
+) -> Result<(), NnioError> {
+    let addr: SocketAddr = format!("{}:{}", cfg.ip, cfg.port)
+        .parse()
+        .map_err(|e| NnioError::CustomError(format!("Invalid HTTP address : {}", e)))?;
+
+    let app = Router::new()
+        .route("/models", get(list_models).post(create_model))
+        .route("/models/:name", get(model_info).delete(delete_model))
+        .route("/models/:name/load", post(load_model))
+        .route("/models/:name/evaluate", post(evaluate))
+        .route("/models/:name/train", post(submit_train_job))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(job_status))
+        .route("/jobs/:id/result", get(job_result))
+        .route("/ws", get(websocket))
+        .route_layer(middleware::from_fn(rate_limit))
+        .with_state(Gateway {
+            auth,
+            mdls,
+            dsets,
+            jobs,
+            limits,
+            stop_rx: stop_rx.clone(),
+        });
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| NnioError::CustomError(format!("Failed to bind {} : {}", addr, e)))?;
+
+    info!("HTTP gateway is listening on {}", addr);
+
+    let (conn_tx, conn_rx) = mpsc::channel(1);
+    let accept = accept_http(listener, limits, connections, tls, conn_tx);
+
+    let server = axum::Server::builder(GatewayIncoming(conn_rx))
+        .serve(app.into_make_service_with_connect_info::<ConnState>())
+        .with_graceful_shutdown(async move {
+            let _ = stop_rx.wait_for(|stop| *stop).await;
+        });
+
+    // accepting stops along with the server
+    tokio::select! {
+        res = server => res.map_err(|e| NnioError::CustomError(format!("HTTP server failed : {}", e))),
+        _ = accept => Ok(()),
+    }
+}
+
+/// Accepts HTTP connections, counting them against `connections`, and
+/// hands them to hyper once the TLS handshake is done
+async fn accept_http(
+    listener: TcpListener,
+    limits: ConnectionLimits,
+    connections: Arc<Semaphore>,
+    tls: Option<TlsAcceptor>,
+    conn_tx: mpsc::Sender<GatewayConn>,
+) {
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(vals) => vals,
+            Err(e) => {
+                error!("Failed to accept HTTP connection : {}", e);
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!("Rejecting HTTP client {} : connection limit reached", peer);
+                continue;
+            }
+        };
+
+        let tls = tls.clone();
+        let conn_tx = conn_tx.clone();
+
+        tokio::spawn(async move {
+            let stream: Box<dyn AsyncStream> = match tls {
+                Some(acceptor) => {
+                    match accept_tls(&acceptor, socket, &peer.to_string(), limits).await {
+                        Some(stream) => stream,
+                        None => return,
+                    }
+                }
+                None => Box::new(socket),
+            };
+
+            let state = ConnState {
+                limiter: limits.rate_limiter(),
+                basic: Arc::new(Mutex::new(None)),
+            };
+
+            let _ = conn_tx
+                .send(GatewayConn {
+                    stream,
+                    state,
+                    _permit: permit,
+                })
+                .await;
+        });
+    }
+}
+
+/// Connections from `accept_http` as hyper takes them
+struct GatewayIncoming(mpsc::Receiver<GatewayConn>);
+
+impl Accept for GatewayIncoming {
+    type Conn = GatewayConn;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+/// HTTP connection holding its `max_con` permit, an upgraded WebSocket
+/// keeps it until the socket closes
+struct GatewayConn {
+    stream: Box<dyn AsyncStream>,
+    state: ConnState,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for GatewayConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for GatewayConn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// State of one HTTP connection, shared by all of its requests.
+/// Available to handlers as `ConnectInfo`
+#[derive(Clone)]
+struct ConnState {
+    limiter: Option<Arc<Mutex<RateLimiter>>>,
+    /// Last `Basic` login verified on this connection, so keep-alive clients
+    /// don't pay for password hashing on every request
+    basic: Arc<Mutex<Option<BasicLogin>>>,
+}
+
+struct BasicLogin {
+    user: String,
+    password: String,
+    session: Session,
+}
+
+impl ConnState {
+    fn try_acquire(&self) -> bool {
+        match &self.limiter {
+            Some(limiter) => limiter.lock().unwrap().try_acquire(),
+            None => true,
+        }
+    }
+
+    /// Session of an already verified `Basic` login
+    fn cached_session(&self, user: &str, password: &str) -> Option<Session> {
+        self.basic
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|login| {
+                login.user == user
+                    && constant_time_eq(login.password.as_bytes(), password.as_bytes())
+            })
+            .map(|login| login.session.clone())
+    }
+}
+
+impl Connected<&GatewayConn> for ConnState {
+    fn connect_info(conn: &GatewayConn) -> Self {
+        conn.state.clone()
+    }
+}
+
+async fn rate_limit<B>(
+    ConnectInfo(state): ConnectInfo<ConnState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> HttpResponse {
+    if !state.try_acquire() {
+        return ApiError(NnioError::RateLimited).into_response();
+    }
+
+    next.run(req).await
+}
+
+/// `NnioError` as an HTTP status with the same `code` as `RespError`
+struct ApiError(NnioError);
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: u32,
+    message: String,
+}
+
+impl From<NnioError> for ApiError {
+    fn from(e: NnioError) -> Self {
+        ApiError(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> HttpResponse {
+        let status = match &self.0 {
+            NnioError::ModelNotExists
+            | NnioError::CheckpointNotExists
+            | NnioError::DatasetNotExists
+            | NnioError::JobNotExists => StatusCode::NOT_FOUND,
+            NnioError::ModelAlreadyExists
+            | NnioError::DatasetAlreadyExists
+            | NnioError::ModelAlreadyLoaded
+            | NnioError::ModelNotLoaded
+            | NnioError::JobNotFinished => StatusCode::CONFLICT,
+            NnioError::InvalidRequest(_)
+            | NnioError::SizeMismatch { .. }
+            | NnioError::ProtocolMismatch { .. }
+            | NnioError::HandshakeRequired => StatusCode::BAD_REQUEST,
+            NnioError::AuthFailed | NnioError::AuthRequired => StatusCode::UNAUTHORIZED,
+            NnioError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            NnioError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            NnioError::ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+            NnioError::ModelFailed(_)
+            | NnioError::ModelCommunication
+            | NnioError::Io(_)
+            | NnioError::CustomError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let body = ErrorBody {
+            code: self.0.code(),
+            message: self.0.to_string(),
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Session of the `Authorization` header, `Bearer` for tokens and `Basic`
+/// for users, checked the same way as `Hello` credentials. A `Basic` login
+/// is hashed once per connection
+struct Auth(Session);
+
+#[async_trait]
+impl FromRequestParts<Gateway> for Auth {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, gw: &Gateway) -> Result<Self, Self::Rejection> {
+        if let Some(bearer) = parts.headers.typed_get::<Authorization<Bearer>>() {
+            let credentials = Credentials::Token(bearer.token().to_owned());
+            return Ok(Auth(gw.auth.clone().verify(Some(credentials)).await?));
+        }
+
+        let basic = match parts.headers.typed_get::<Authorization<Basic>>() {
+            Some(basic) => basic,
+            None => return Ok(Auth(gw.auth.clone().verify(None).await?)),
+        };
+
+        let state = parts
+            .extensions
+            .get::<ConnectInfo<ConnState>>()
+            .map(|info| info.0.clone());
+
+        if let Some(session) = state
+            .as_ref()
+            .and_then(|state| state.cached_session(basic.username(), basic.password()))
+        {
+            return Ok(Auth(session));
+        }
+
+        let credentials = Credentials::Password {
+            user: basic.username().to_owned(),
+            password: basic.password().to_owned(),
+        };
+        let session = gw.auth.clone().verify(Some(credentials)).await?;
+
+        if let Some(state) = state {
+            *state.basic.lock().unwrap() = Some(BasicLogin {
+                user: basic.username().to_owned(),
+                password: basic.password().to_owned(),
+                session: session.clone(),
+            });
+        }
+
+        Ok(Auth(session))
+    }
+}
+
+/// Body of `POST /models`
+#[derive(Deserialize)]
+struct CreateModelBody {
+    name: String,
+    net_cfg: String,
+    #[serde(default)]
+    overwrite: bool,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct LoadModelBody {
+    checkpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EvaluateBody {
+    #[serde(default)]
+    inputs: Vec<Vec<f32>>,
+    /// Evaluates the inputs of a stored dataset instead
+    #[serde(default)]
+    dataset: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EvaluateResult {
+    outputs: Vec<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+struct TrainBody {
+    data: TrainData,
+    #[serde(flatten)]
+    params: TrainParams,
+}
+
+#[derive(Serialize)]
+struct SubmittedJob {
+    job_id: u64,
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    #[serde(default)]
+    keep_trash: bool,
+}
+
+async fn list_models(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+) -> Result<Json<Vec<String>>, ApiError> {
+    session.check(MessageType::GetAvailableModels)?;
+
+    Ok(Json(gw.mdls.get_availabel_models()))
+}
+
+async fn model_info(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(name): Path<String>,
+) -> Result<Json<ModelInfo>, ApiError> {
+    session.check(MessageType::ModelInfo)?;
+
+    Ok(Json(gw.mdls.get_model_info(&name).await?))
+}
+
+async fn create_model(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Json(body): Json<CreateModelBody>,
+) -> Result<StatusCode, ApiError> {
+    session.check(MessageType::CreateModel)?;
+
+    gw.mdls
+        .create_model(
+            body.net_cfg,
+            body.name,
+            body.overwrite,
+            body.description,
+            body.tags,
+        )
+        .await?;
+
+    Ok(StatusCode::CREATED)
+}
+
+async fn load_model(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(name): Path<String>,
+    body: Option<Json<LoadModelBody>>,
+) -> Result<StatusCode, ApiError> {
+    session.check(MessageType::LoadModel)?;
+
+    let Json(body) = body.unwrap_or_default();
+
+    gw.mdls.load_model(name, body.checkpoint).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn evaluate(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(name): Path<String>,
+    Json(body): Json<EvaluateBody>,
+) -> Result<Json<EvaluateResult>, ApiError> {
+    session.check(MessageType::EvaluateData)?;
+
+    let inputs = Listener::eval_inputs(&gw.dsets, body.inputs, body.dataset).await?;
+    let outputs = gw.mdls.evaluate_data(&name, inputs).await?;
+
+    Ok(Json(EvaluateResult { outputs }))
+}
+
+async fn delete_model(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(name): Path<String>,
+    Query(params): Query<DeleteParams>,
+) -> Result<StatusCode, ApiError> {
+    session.check(MessageType::DeleteModel)?;
+
+    gw.mdls.delete_model(&name, params.keep_trash).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Answers 202 with the job id, training goes on in the background
+async fn submit_train_job(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(name): Path<String>,
+    Json(body): Json<TrainBody>,
+) -> Result<(StatusCode, Json<SubmittedJob>), ApiError> {
+    session.check(MessageType::SubmitTrainJob)?;
+
+    let job_id = gw.jobs.submit(name, body.data, body.params).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(SubmittedJob { job_id })))
+}
+
+async fn list_jobs(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+) -> Result<Json<Vec<JobInfo>>, ApiError> {
+    session.check(MessageType::ListJobs)?;
+
+    Ok(Json(gw.jobs.list_jobs()))
+}
+
+async fn job_status(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(id): Path<u64>,
+) -> Result<Json<JobInfo>, ApiError> {
+    session.check(MessageType::JobStatus)?;
+
+    Ok(Json(gw.jobs.job_info(id)?))
+}
+
+async fn job_result(
+    State(gw): State<Gateway>,
+    Auth(session): Auth,
+    Path(id): Path<u64>,
+) -> Result<Json<TrainResult>, ApiError> {
+    session.check(MessageType::JobResult)?;
+
+    Ok(Json(gw.jobs.job_result(id)?))
+}
+
+async fn websocket(
+    ws: WebSocketUpgrade,
+    State(gw): State<Gateway>,
+    ConnectInfo(state): ConnectInfo<ConnState>,
+) -> HttpResponse {
+    ws.max_message_size(gw.limits.max_frame_size)
+        .max_frame_size(gw.limits.max_frame_size)
+        .on_upgrade(move |socket| handle_ws(socket, gw, state))
+}
+
+/// Speaks the framed protocol with one JSON frame per text message, and
+/// pushes `RespEvent` to clients allowed to see models. Requests share the
+/// rate limit of the HTTP connection
+async fn handle_ws(mut socket: WebSocket, gw: Gateway, state: ConnState) {
+    let limits = gw.limits;
+
+    let handshake = ws_handshake(&mut socket, &gw.auth);
+
+    let session = match with_timeout(limits.idle_timeout, handshake).await {
+        Some(Some(session)) => session,
+        Some(None) => return,
+        None => {
+            debug!("WebSocket handshake timed out");
+            return;
+        }
+    };
+
+    let mut events = session
+        .check(MessageType::ModelInfo)
+        .is_ok()
+        .then(|| gw.mdls.subscribe());
+
+    let (dispatcher, mut resp_rx) = Dispatcher::new(
+        session,
+        state.limiter.clone(),
+        limits,
+        gw.mdls.clone(),
+        gw.dsets.clone(),
+        gw.jobs.clone(),
+    );
+
+    let (mut sink, mut stream) = socket.split();
+
+    // responses may come out of order, a single task owns the sending half
+    let writer_task = tokio::spawn(async move {
+        while let Some(frame) = resp_rx.recv().await {
+            if !send_frame(&mut sink, &frame).await {
+                break;
+            }
+        }
+
+        let _ = sink.close().await;
+    });
+
+    let mut stop_rx = gw.stop_rx.clone();
+
+    // any message from the client, pings included, keeps the socket open
+    let idle = idle_timer(limits.idle_timeout);
+    tokio::pin!(idle);
+
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                idle.as_mut().set(idle_timer(limits.idle_timeout));
+
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    // pings are answered by axum
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("WebSocket receive failed : {}", e);
+                        break;
+                    }
+                };
+
+                if !dispatcher.dispatch(text.into_bytes()).await {
+                    break;
+                }
+            }
+            _ = &mut idle => {
+                if dispatcher.is_busy() {
+                    idle.as_mut().set(idle_timer(limits.idle_timeout));
+                    continue;
+                }
+
+                debug!("Closing idle WebSocket");
+                break;
+            }
+            event = next_event(&mut events) => {
+                let frame = ResponseFrame {
+                    id: UNSOLICITED_ID,
+                    resp: Response::RespEvent { event },
+                };
+
+                if !dispatcher.send(frame).await {
+                    break;
+                }
+            }
+            _ = stop_rx.changed() => {
+                let notice = ResponseFrame {
+                    id: UNSOLICITED_ID,
+                    resp: Response::RespServerShutdown,
+                };
+                dispatcher.send(notice).await;
+                break;
+            }
+        }
+    }
+
+    drop(dispatcher);
+    writer_task.await.ok();
+}
+
+/// Expects `Hello` as the first message, like `Listener::handshake`
+async fn ws_handshake(socket: &mut WebSocket, auth: &Arc<AuthConfig>) -> Option<Session> {
+    let text = match socket.recv().await {
+        Some(Ok(Message::Text(text))) => text,
+        _ => return None,
+    };
+
+    let (res, resp) = Listener::greet_frame(text.as_bytes(), auth).await;
+
+    if !send_frame(socket, &resp).await {
+        return None;
+    }
+
+    match res {
+        Ok(session) => Some(session),
+        Err(e) => {
+            warn!("WebSocket handshake failed : {}", e);
+            None
+        }
+    }
+}
+
+async fn send_frame<S>(sink: &mut S, frame: &ResponseFrame) -> bool
+where
+    S: Sink<Message> + Unpin,
+{
+    let text = match serde_json::to_string(frame) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Failed to encode response : {}", e);
+            return false;
+        }
+    };
+
+    sink.send(Message::Text(text)).await.is_ok()
+}
+
+/// Fires after `dur` without a message, never without `dur`
+async fn idle_timer(dur: Option<Duration>) {
+    match dur {
+        Some(dur) => tokio::time::sleep(dur).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Never resolves without a subscription or once the storage is gone
+async fn next_event(events: &mut Option<broadcast::Receiver<ServerEvent>>) -> ServerEvent {
+    if let Some(rx) = events {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("WebSocket client missed {} events", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+
+    std::future::pending().await
+}
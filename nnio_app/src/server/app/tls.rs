@@ -0,0 +1,207 @@
+use std::{io, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio_rustls::{
+    rustls::{server::AllowAnyAuthenticatedClient, ServerConfig},
+    TlsAcceptor,
+};
+
+use nnio_common::tls::*;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// PEM certificate chain of the server
+    pub cert_path: PathBuf,
+    /// PEM private key of the server
+    pub key_path: PathBuf,
+    /// Clients must present a certificate signed by this PEM CA if set
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// `None` if TLS is disabled
+    pub fn acceptor(&self) -> io::Result<Option<TlsAcceptor>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+
+        let builder = match &self.client_ca_path {
+            Some(ca_path) => builder.with_client_cert_verifier(
+                AllowAnyAuthenticatedClient::new(load_root_store(ca_path)?).boxed(),
+            ),
+            None => builder.with_no_client_auth(),
+        };
+
+        let cfg = builder
+            .with_single_cert(
+                load_certs(&self.cert_path)?,
+                load_private_key(&self.key_path)?,
+            )
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Some(TlsAcceptor::from(Arc::new(cfg))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{ClientConfig, ServerName},
+        TlsConnector,
+    };
+
+    use super::*;
+
+    /// CA, server and client certificates written as PEM files
+    struct Pki {
+        dir: PathBuf,
+    }
+
+    impl Pki {
+        fn generate(test: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("nnio_tls_{}_{}", std::process::id(), test));
+            fs::create_dir_all(&dir).unwrap();
+
+            let mut ca_params = CertificateParams::new(vec![]);
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = Certificate::from_params(ca_params).unwrap();
+
+            fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+            for (name, alt_name) in [("server", "localhost"), ("client", "client")] {
+                let cert = Certificate::from_params(CertificateParams::new(vec![alt_name.into()]))
+                    .unwrap();
+
+                fs::write(
+                    dir.join(format!("{}.pem", name)),
+                    cert.serialize_pem_with_signer(&ca).unwrap(),
+                )
+                .unwrap();
+                fs::write(
+                    dir.join(format!("{}.key", name)),
+                    cert.serialize_private_key_pem(),
+                )
+                .unwrap();
+            }
+
+            Self { dir }
+        }
+
+        fn server_cfg(&self, mtls: bool) -> TlsConfig {
+            TlsConfig {
+                enabled: true,
+                cert_path: self.dir.join("server.pem"),
+                key_path: self.dir.join("server.key"),
+                client_ca_path: mtls.then(|| self.dir.join("ca.pem")),
+            }
+        }
+
+        fn connector(&self, client_cert: bool) -> TlsConnector {
+            let builder = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(load_root_store(&self.dir.join("ca.pem")).unwrap());
+
+            let cfg = if client_cert {
+                builder
+                    .with_client_auth_cert(
+                        load_certs(&self.dir.join("client.pem")).unwrap(),
+                        load_private_key(&self.dir.join("client.key")).unwrap(),
+                    )
+                    .unwrap()
+            } else {
+                builder.with_no_client_auth()
+            };
+
+            TlsConnector::from(Arc::new(cfg))
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Runs both sides of a handshake, then sends one byte from the client
+    /// to check that the session works
+    async fn handshake(server: &TlsConfig, connector: TlsConnector) -> io::Result<()> {
+        let acceptor = server.acceptor()?.expect("TLS is enabled");
+        let (client_io, server_io) = duplex(16 * 1024);
+
+        let accept = async {
+            let mut stream = acceptor.accept(server_io).await?;
+            let mut buf = [0u8; 1];
+            stream.read_exact(&mut buf).await?;
+            Ok::<u8, io::Error>(buf[0])
+        };
+
+        let connect = async {
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(name, client_io).await?;
+            stream.write_all(&[42]).await?;
+            stream.flush().await?;
+            Ok::<_, io::Error>(stream)
+        };
+
+        let (accepted, connected) = tokio::join!(accept, connect);
+
+        // keep the client open until the server has read the byte
+        let _client = connected?;
+        assert_eq!(accepted?, 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_gives_no_acceptor() {
+        assert!(TlsConfig::default().acceptor().unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let cfg = TlsConfig {
+            enabled: true,
+            cert_path: Path::new("/nonexistent/server.pem").into(),
+            key_path: Path::new("/nonexistent/server.key").into(),
+            client_ca_path: None,
+        };
+
+        assert!(cfg.acceptor().is_err());
+    }
+
+    #[tokio::test]
+    async fn accepts_plain_tls() {
+        let pki = Pki::generate("plain");
+
+        handshake(&pki.server_cfg(false), pki.connector(false))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn accepts_client_certificate() {
+        let pki = Pki::generate("mtls");
+
+        handshake(&pki.server_cfg(true), pki.connector(true))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_client_certificate() {
+        let pki = Pki::generate("mtls_rejected");
+
+        assert!(handshake(&pki.server_cfg(true), pki.connector(false))
+            .await
+            .is_err());
+    }
+}
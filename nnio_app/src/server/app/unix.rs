@@ -0,0 +1,69 @@
+use std::{
+    fs, io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UnixListener;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnixSocketConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Octal permissions of the socket file, e.g. "660"
+    pub mode: String,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("/tmp/nnio.sock"),
+            mode: String::from("660"),
+        }
+    }
+}
+
+impl UnixSocketConfig {
+    /// `None` if the socket is disabled. A socket file left by a previous run
+    /// is replaced, any other file at `path` is an error
+    pub fn bind(&self) -> io::Result<Option<UnixListener>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let mode = u32::from_str_radix(&self.mode, 8).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid socket mode {} : {}", self.mode, e),
+            )
+        })?;
+
+        match fs::symlink_metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_socket() => fs::remove_file(&self.path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", self.path.display()),
+                ))
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+
+        let listener = UnixListener::bind(&self.path)?;
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(mode))?;
+
+        Ok(Some(listener))
+    }
+
+    pub fn cleanup(&self) {
+        if self.enabled {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove {} : {}", self.path.display(), e);
+            }
+        }
+    }
+}
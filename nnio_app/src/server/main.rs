@@ -9,8 +9,14 @@ use app::*;
 
 async fn handle_shutdown(shutdown_tx: mpsc::Sender<()>) -> Result<(), Box<dyn Error>> {
     let mut sigint = signal(SignalKind::interrupt())?;
-    sigint.recv().await;
-    shutdown_tx.send(()).await.unwrap();
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = sigint.recv() => info!("Received SIGINT"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
+
+    shutdown_tx.send(()).await?;
 
     Ok(())
 }
@@ -31,7 +37,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     });
 
-    let mut listener = Listener::new(App::from_config_or_default());
+    let mut listener = Listener::new(App::from_config_or_default()?);
     listener.run(cancel_rx).await;
 
     Ok(())
@@ -0,0 +1,269 @@
+use std::io;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the frame header, payload length as big-endian u32
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Plain or TLS connection, boxed as `Box<dyn AsyncStream>`
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// Length-prefixed framing used by both server and client.
+///
+/// Each frame on the wire is `[len: u32 BE][payload: len bytes]`.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Reads a single frame payload.
+    ///
+    /// Returns `Ok(None)` if the peer closed the connection between frames.
+    pub async fn read_frame<R>(&self, reader: &mut R) -> io::Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        match self.read_header(reader).await? {
+            Some(len) => Ok(Some(self.read_payload(reader, len).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a frame header and returns the checked payload length.
+    ///
+    /// Returns `Ok(None)` if the peer closed the connection between frames.
+    pub async fn read_header<R>(&self, reader: &mut R) -> io::Result<Option<usize>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        let mut read = 0;
+
+        while read < FRAME_HEADER_SIZE {
+            match reader.read(&mut header[read..]).await? {
+                0 if read == 0 => return Ok(None),
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Connection closed after {} header bytes", read),
+                    ))
+                }
+                n => read += n,
+            }
+        }
+
+        let len = u32::from_be_bytes(header) as usize;
+
+        if len > self.max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frame size {} exceeds maximum of {} bytes",
+                    len, self.max_frame_size
+                ),
+            ));
+        }
+
+        Ok(Some(len))
+    }
+
+    /// Reads the payload following a header returned by `read_header`
+    pub async fn read_payload<R>(&self, reader: &mut R, len: usize) -> io::Result<Vec<u8>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await?;
+
+        Ok(payload)
+    }
+
+    pub async fn write_frame<W>(&self, writer: &mut W, payload: &[u8]) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        if payload.len() > self.max_frame_size || payload.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Frame size {} exceeds maximum of {} bytes",
+                    payload.len(),
+                    self.max_frame_size
+                ),
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(payload);
+
+        writer.write_all(&buf).await?;
+        writer.flush().await
+    }
+
+    /// Reads a frame and decodes it as a JSON message
+    pub async fn read_msg<R, T>(&self, reader: &mut R) -> io::Result<Option<T>>
+    where
+        R: AsyncRead + Unpin,
+        T: DeserializeOwned,
+    {
+        match self.read_frame(reader).await? {
+            Some(frame) => Ok(Some(decode_msg(&frame)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn write_msg<W, T>(&self, writer: &mut W, msg: &T) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+        T: Serialize,
+    {
+        let payload = serde_json::to_vec(msg)?;
+        self.write_frame(writer, &payload).await
+    }
+}
+
+pub fn decode_msg<T: DeserializeOwned>(frame: &[u8]) -> io::Result<T> {
+    serde_json::from_slice(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::ReadBuf;
+
+    use super::*;
+
+    /// Hands out one byte per read, like a peer sending the frame in pieces
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if let Some((first, rest)) = self.0.split_first() {
+                buf.put_slice(&[*first]);
+                self.0 = rest;
+            }
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut buf = (payload.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[tokio::test]
+    async fn reads_split_frame() {
+        let data = frame(b"hello");
+        let mut reader = Trickle(&data);
+
+        let codec = FrameCodec::default();
+
+        assert_eq!(
+            codec.read_frame(&mut reader).await.unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(codec.read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reads_merged_frames() {
+        let mut data = frame(b"first");
+        data.extend(frame(b""));
+        data.extend(frame(b"third"));
+        let mut reader = &data[..];
+
+        let codec = FrameCodec::default();
+
+        assert_eq!(
+            codec.read_frame(&mut reader).await.unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(
+            codec.read_frame(&mut reader).await.unwrap(),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            codec.read_frame(&mut reader).await.unwrap(),
+            Some(b"third".to_vec())
+        );
+        assert_eq!(codec.read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frame() {
+        let data = frame(&[0u8; 17]);
+        let codec = FrameCodec::new(16);
+
+        let err = codec.read_frame(&mut &data[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut out = Vec::new();
+        let err = codec.write_frame(&mut out, &[0u8; 17]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_header_is_an_error() {
+        let codec = FrameCodec::default();
+
+        let err = codec.read_header(&mut &[0u8, 0][..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn truncated_payload_is_an_error() {
+        let data = frame(b"hello");
+        let codec = FrameCodec::default();
+
+        let err = codec.read_frame(&mut &data[..6]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn round_trips_messages() {
+        let codec = FrameCodec::default();
+        let mut out = Vec::new();
+
+        codec.write_msg(&mut out, &vec![1, 2, 3]).await.unwrap();
+        codec.write_msg(&mut out, &vec![4]).await.unwrap();
+
+        let mut reader = &out[..];
+        let first: Option<Vec<u32>> = codec.read_msg(&mut reader).await.unwrap();
+        let second: Option<Vec<u32>> = codec.read_msg(&mut reader).await.unwrap();
+
+        assert_eq!(first, Some(vec![1, 2, 3]));
+        assert_eq!(second, Some(vec![4]));
+    }
+}
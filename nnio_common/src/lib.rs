@@ -1,30 +1,79 @@
-use strum_macros::{EnumIter, Display};
+pub mod codec;
+pub mod message;
+pub mod tls;
 
-#[derive(Debug, EnumIter)]
+pub use codec::*;
+pub use message::*;
+
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Protocol version exchanged in the `Hello`/`RespWelcome` handshake,
+/// bump on any incompatible change of the wire format
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Discriminants are pinned, never reorder or reuse them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
 pub enum MessageType {
-    GetAvailableModels,
-    GetLoadedModels,
-    ModelInfo,
-    CreateModel,
-    DeleteModel,
-    LoadModel,
-    UnloadModel,
-    SaveModelCfg,
-    SaveModelState,
-    TrainModel,
-    EvaluateData,
-    Exit,
+    GetAvailableModels = 0,
+    GetLoadedModels = 1,
+    ModelInfo = 2,
+    CreateModel = 3,
+    DeleteModel = 4,
+    LoadModel = 5,
+    UnloadModel = 6,
+    SaveModelCfg = 7,
+    SaveModelState = 8,
+    TrainModel = 9,
+    EvaluateData = 10,
+    Exit = 11,
 
     // Response
-    RespModelCreateSuccess,
-    RespModelCreateFailure,
-    RespModelInfoSuccess,
-    RespModelInfoFailure,
-    RespAvailableModels,
-    RespLoadModel,
-    RespLoadedModels,
-    RespModelInfo,
-    RespModelSaveCfg,
+    RespModelCreateSuccess = 12,
+    RespModelCreateFailure = 13,
+    RespModelInfoSuccess = 14,
+    RespModelInfoFailure = 15,
+    RespAvailableModels = 16,
+    RespLoadModel = 17,
+    RespLoadedModels = 18,
+    RespModelInfo = 19,
+    RespModelSaveCfg = 20,
+    RespError = 21,
+
+    // Handshake
+    Hello = 22,
+    RespWelcome = 23,
+
+    RespTrainModel = 24,
+    RespEvaluateData = 25,
+    RestoreModel = 26,
+    RespDeleteModel = 27,
+    RespRestoreModel = 28,
+    RespUnloadModel = 29,
+    ListCheckpoints = 30,
+    RespSaveModelState = 31,
+    RespCheckpoints = 32,
+    RespServerShutdown = 33,
+    RespEvent = 34,
+    UploadDataset = 35,
+    ListDatasets = 36,
+    DeleteDataset = 37,
+    RespUploadDataset = 38,
+    RespDatasets = 39,
+    RespDeleteDataset = 40,
+    UploadDatasetFile = 41,
+    ImportDataset = 42,
+    RespUploadDatasetFile = 43,
+    RespImportDataset = 44,
+    SubmitTrainJob = 45,
+    JobStatus = 46,
+    ListJobs = 47,
+    JobResult = 48,
+    RespSubmitTrainJob = 49,
+    RespJobStatus = 50,
+    RespJobs = 51,
+    RespJobResult = 52,
 }
 
 impl fmt::Display for MessageType {
@@ -37,27 +86,14 @@ impl TryFrom<&str> for MessageType {
     type Error = Box<dyn std::error::Error + Send>;
 
     fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
-        if value == MessageType::GetAvailableModels.to_string() {
-            return Ok(MessageType::GetAvailableModels);
-        } else if value == MessageType::GetAvailableModels.to_string() {
-            return Ok(MessageType::GetLoadedModels);
-        } else if value == MessageType::ModelInfo.to_string() {
-            return Ok(MessageType::ModelInfo);
-        } else if value == MessageType::CreateModel.to_string() {
-            return Ok(MessageType::CreateModel);
-        } else if value == MessageType::DeleteModel.to_string() {
-            return Ok(MessageType::DeleteModel);
-        } else if value == MessageType::TrainModel.to_string() {
-            return Ok(MessageType::TrainModel);
-        } else if value == MessageType::EvaluateData.to_string() {
-            return Ok(MessageType::EvaluateData);
-        } else if value == MessageType::UnloadModel.to_string() {
-            return Ok(MessageType::UnloadModel);
-        } else if value == MessageType::LoadModel.to_string() {
-            return Ok(MessageType::LoadModel);
-        } else {
-            todo!("error");
-        }
+        MessageType::iter()
+            .find(|t| t.to_string() == value)
+            .ok_or_else(|| -> Self::Error {
+                Box::new(NnioError::InvalidRequest(format!(
+                    "Unknown message type {}",
+                    value
+                )))
+            })
     }
 }
 
@@ -65,59 +101,130 @@ impl TryFrom<u64> for MessageType {
     type Error = Box<dyn std::error::Error + Send>;
 
     fn try_from(value: u64) -> std::result::Result<Self, Self::Error> {
-        if value == MessageType::GetAvailableModels as u64 {
-            return Ok(MessageType::GetAvailableModels);
-        } else if value == MessageType::GetLoadedModels as u64 {
-            return Ok(MessageType::GetLoadedModels);
-        } else if value == MessageType::ModelInfo as u64 {
-            return Ok(MessageType::ModelInfo);
-        } else if value == MessageType::CreateModel as u64 {
-            return Ok(MessageType::CreateModel);
-        } else if value == MessageType::DeleteModel as u64  {
-            return Ok(MessageType::DeleteModel);
-        } else if value == MessageType::TrainModel as u64 {
-            return Ok(MessageType::TrainModel);
-        } else if value == MessageType::EvaluateData as u64 {
-            return Ok(MessageType::EvaluateData);
-        } else if value == MessageType::UnloadModel as u64 {
-            return Ok(MessageType::UnloadModel);
-        } else if value == MessageType::LoadModel as u64 {
-            return Ok(MessageType::LoadModel);
-        } else if value == MessageType::SaveModelCfg as u64 {
-            return Ok(MessageType::SaveModelCfg);
-        } else if value == MessageType::SaveModelState as u64 {
-            return Ok(MessageType::SaveModelState);
-        }  else {
-            todo!("error");
-        }
+        MessageType::iter()
+            .find(|t| *t as u64 == value)
+            .ok_or_else(|| -> Self::Error {
+                Box::new(NnioError::InvalidRequest(format!(
+                    "Unknown message type {}",
+                    value
+                )))
+            })
     }
 }
 
 use std::{fmt, error::Error};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NnioError {
     ModelNotExists,
     ModelAlreadyExists,
     ModelCommunication,
     ModelNotLoaded,
     ModelAlreadyLoaded,
+    InvalidRequest(String),
+    Io(String),
+    ProtocolMismatch { server: u32, client: u32 },
+    HandshakeRequired,
+    SizeMismatch { expected: usize, got: usize },
+    CheckpointNotExists,
+    /// Model worker died, holds the panic message
+    ModelFailed(String),
+    ServerBusy,
+    RateLimited,
+    AuthFailed,
+    AuthRequired,
+    PermissionDenied(MessageType),
+    DatasetNotExists,
+    DatasetAlreadyExists,
+    JobNotExists,
+    /// Job is still queued or running
+    JobNotFinished,
     CustomError(String),
+}
 
+impl NnioError {
+    /// Stable numeric code sent to clients in `RespError`, never reuse or renumber
+    pub fn code(&self) -> u32 {
+        match self {
+            NnioError::ModelNotExists => 1,
+            NnioError::ModelAlreadyExists => 2,
+            NnioError::ModelCommunication => 3,
+            NnioError::ModelNotLoaded => 4,
+            NnioError::ModelAlreadyLoaded => 5,
+            NnioError::InvalidRequest(_) => 6,
+            NnioError::Io(_) => 7,
+            NnioError::ProtocolMismatch { .. } => 8,
+            NnioError::HandshakeRequired => 9,
+            NnioError::SizeMismatch { .. } => 10,
+            NnioError::CheckpointNotExists => 11,
+            NnioError::ModelFailed(_) => 12,
+            NnioError::ServerBusy => 13,
+            NnioError::RateLimited => 14,
+            NnioError::AuthFailed => 15,
+            NnioError::AuthRequired => 16,
+            NnioError::PermissionDenied(_) => 17,
+            NnioError::DatasetNotExists => 18,
+            NnioError::DatasetAlreadyExists => 19,
+            NnioError::JobNotExists => 20,
+            NnioError::JobNotFinished => 21,
+            NnioError::CustomError(_) => 1000,
+        }
+    }
 }
 
 impl fmt::Display for NnioError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            NnioError::CustomError(msg) => {
-                write!(f, "Custom Error : {}", msg)
-            },
-            _ => {
-                write!(f, "{}", "Other")
-            },
+            NnioError::ModelNotExists => write!(f, "Model doesn't exist"),
+            NnioError::ModelAlreadyExists => write!(f, "Model already exists"),
+            NnioError::ModelCommunication => {
+                write!(f, "Failed to communicate with the model worker")
+            }
+            NnioError::ModelNotLoaded => write!(f, "Model is not loaded"),
+            NnioError::ModelAlreadyLoaded => write!(f, "Model is already loaded"),
+            NnioError::InvalidRequest(msg) => write!(f, "Invalid request : {}", msg),
+            NnioError::Io(msg) => write!(f, "I/O error : {}", msg),
+            NnioError::ProtocolMismatch { server, client } => write!(
+                f,
+                "Protocol version mismatch : server speaks {}, client speaks {}",
+                server, client
+            ),
+            NnioError::HandshakeRequired => {
+                write!(f, "Handshake required, send Hello first")
+            }
+            NnioError::SizeMismatch { expected, got } => write!(
+                f,
+                "Data size mismatch : expected {} values, got {}",
+                expected, got
+            ),
+            NnioError::CheckpointNotExists => write!(f, "Checkpoint doesn't exist"),
+            NnioError::ModelFailed(msg) => {
+                write!(f, "Model worker failed, load the model again : {}", msg)
+            }
+            NnioError::ServerBusy => {
+                write!(f, "Server connection limit reached, try again later")
+            }
+            NnioError::RateLimited => write!(f, "Too many requests, slow down"),
+            NnioError::AuthFailed => write!(f, "Authentication failed"),
+            NnioError::AuthRequired => {
+                write!(f, "Request requires an authenticated connection")
+            }
+            NnioError::PermissionDenied(msg_type) => {
+                write!(f, "Role of this connection doesn't allow {}", msg_type)
+            }
+            NnioError::DatasetNotExists => write!(f, "Dataset doesn't exist"),
+            NnioError::DatasetAlreadyExists => write!(f, "Dataset already exists"),
+            NnioError::JobNotExists => write!(f, "Job doesn't exist"),
+            NnioError::JobNotFinished => write!(f, "Job hasn't finished yet"),
+            NnioError::CustomError(msg) => write!(f, "Custom Error : {}", msg),
         }
     }
 }
 
-impl Error for NnioError {}
+impl From<std::io::Error> for NnioError {
+    fn from(err: std::io::Error) -> Self {
+        NnioError::Io(err.to_string())
+    }
+}
 
+impl Error for NnioError {}
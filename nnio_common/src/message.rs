@@ -0,0 +1,515 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{MessageType, NnioError};
+
+/// Single labeled training sample
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub input: Vec<f32>,
+    pub expected: Vec<f32>,
+}
+
+/// Where the training samples come from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrainData {
+    Samples(Vec<Sample>),
+    /// Name of a dataset uploaded with `UploadDataset`
+    Dataset(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainParams {
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// Changes the learning rate of the loaded model, later runs without it
+    /// keep the last one set. Loading the model again resets it
+    #[serde(default)]
+    pub learning_rate: Option<f32>,
+}
+
+/// Loss metrics of a finished training run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrainResult {
+    pub epochs: usize,
+    /// Mean squared error over the training samples after each epoch
+    pub epoch_loss: Vec<f64>,
+    pub final_loss: f64,
+}
+
+/// Metadata manifest kept in `meta.yaml` of every model
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelMeta {
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    pub last_trained_at: Option<u64>,
+    pub total_epochs: usize,
+    pub last_loss: Option<f64>,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub param_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LayerInfo {
+    pub layer_type: String,
+    pub size: usize,
+    pub activation: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub name: String,
+    pub loaded: bool,
+    /// Panic message of a dead model worker, cleared by the next load
+    #[serde(default)]
+    pub failure: Option<String>,
+    pub meta: ModelMeta,
+    pub layers: Vec<LayerInfo>,
+}
+
+/// Dataset kept in the server dataset store
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DatasetInfo {
+    pub name: String,
+    pub samples: usize,
+    pub input_size: usize,
+    pub output_size: usize,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// Class of each one-hot output position, empty if labels aren't one-hot
+    #[serde(default)]
+    pub classes: Vec<String>,
+}
+
+/// How `ImportDataset` parses the files sent with `UploadDatasetFile`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Reads file `data`
+    Csv(CsvOptions),
+    /// Reads files `images` and `labels`, MNIST style
+    Idx(IdxOptions),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvOptions {
+    /// Zero-based columns of the input vector
+    pub input_columns: Vec<usize>,
+    /// Zero-based columns of the expected output
+    pub label_columns: Vec<usize>,
+    #[serde(default)]
+    pub has_header: bool,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// Turns the single label column into a one-hot vector
+    #[serde(default)]
+    pub one_hot: bool,
+    /// Label of each one-hot position, found in the data and sorted if empty
+    #[serde(default)]
+    pub classes: Vec<String>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdxOptions {
+    /// Scales byte values to `0.0..=1.0`
+    pub normalize: bool,
+    /// Turns 1-dimensional integer labels into one-hot vectors
+    pub one_hot: bool,
+    /// One-hot vector size, the highest label + 1 if not set
+    pub classes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for earlier jobs of the same model
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Training job submitted with `SubmitTrainJob`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub mdl_name: String,
+    pub state: JobState,
+    /// Last finished epoch, 0 until the first one ends
+    pub epoch: usize,
+    pub epochs: usize,
+    pub last_loss: Option<f64>,
+    /// Why a `Failed` job failed
+    pub error: Option<String>,
+    /// Unix timestamps in seconds
+    pub submitted_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointInfo {
+    pub name: String,
+    pub size: u64,
+    /// Unix timestamp in seconds
+    pub modified: u64,
+}
+
+/// Asynchronous server notification, pushed to WebSocket clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// Sent after every epoch of a running `TrainModel`, `epoch` starts at 1
+    EpochFinished {
+        mdl_name: String,
+        epoch: usize,
+        epochs: usize,
+        loss: f64,
+    },
+    ModelLoaded {
+        mdl_name: String,
+    },
+    ModelUnloaded {
+        mdl_name: String,
+    },
+    /// Model worker panicked, the model must be loaded again
+    ModelFailed {
+        mdl_name: String,
+        message: String,
+    },
+}
+
+/// Sent in `Hello` when the server requires authentication
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Credentials {
+    Token(String),
+    Password { user: String, password: String },
+}
+
+// keeps secrets out of the logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => write!(f, "Token(***)"),
+            Credentials::Password { user, .. } => write!(f, "Password({}, ***)", user),
+        }
+    }
+}
+
+/// Id reserved for messages that don't answer a particular request
+pub const UNSOLICITED_ID: u64 = 0;
+
+/// Request with a client-chosen id, echoed back in the matching `ResponseFrame`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestFrame {
+    pub id: u64,
+    #[serde(flatten)]
+    pub req: Request,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseFrame {
+    pub id: u64,
+    #[serde(flatten)]
+    pub resp: Response,
+}
+
+#[derive(Deserialize)]
+struct FrameId {
+    id: u64,
+}
+
+impl RequestFrame {
+    /// Extracts the id of a frame which couldn't be decoded as a request
+    pub fn peek_id(frame: &[u8]) -> u64 {
+        serde_json::from_slice::<FrameId>(frame)
+            .map(|f| f.id)
+            .unwrap_or(UNSOLICITED_ID)
+    }
+}
+
+/// Client -> server messages, tagged by `type` on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Request {
+    /// Must be the first message of every connection
+    Hello {
+        protocol_version: u32,
+        client_name: String,
+        #[serde(default)]
+        credentials: Option<Credentials>,
+    },
+    GetAvailableModels,
+    GetLoadedModels,
+    ModelInfo {
+        mdl_name: String,
+    },
+    CreateModel {
+        name: String,
+        net_cfg: String,
+        /// Moves the existing model with its checkpoints and history to the trash
+        #[serde(default)]
+        overwrite: bool,
+        #[serde(default)]
+        description: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    DeleteModel {
+        mdl_name: String,
+        /// Move the model to the server trash instead of removing it
+        #[serde(default)]
+        keep_trash: bool,
+    },
+    /// Undoes a `DeleteModel` made with `keep_trash`
+    RestoreModel {
+        mdl_name: String,
+    },
+    LoadModel {
+        mdl_name: String,
+        /// Checkpoint to restore weights from after loading
+        #[serde(default)]
+        checkpoint: Option<String>,
+    },
+    UnloadModel {
+        mdl_name: String,
+        /// Save the model before stopping it
+        #[serde(default)]
+        save: bool,
+    },
+    SaveModelCfg {
+        mdl_name: String,
+    },
+    SaveModelState {
+        mdl_name: String,
+        checkpoint: String,
+    },
+    ListCheckpoints {
+        mdl_name: String,
+    },
+    TrainModel {
+        mdl_name: String,
+        data: TrainData,
+        #[serde(flatten)]
+        params: TrainParams,
+    },
+    /// Evaluates either `inputs` or the inputs of a stored dataset
+    EvaluateData {
+        mdl_name: String,
+        #[serde(default)]
+        inputs: Vec<Vec<f32>>,
+        #[serde(default)]
+        dataset: Option<String>,
+    },
+    /// Chunk `chunk` of a dataset upload, starting at 0. Chunk 0 starts
+    /// a new upload, the dataset is stored once the `last` chunk arrives
+    UploadDataset {
+        name: String,
+        chunk: usize,
+        samples: Vec<Sample>,
+        #[serde(default)]
+        last: bool,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    ListDatasets,
+    DeleteDataset {
+        name: String,
+    },
+    /// Chunk of a raw file for `ImportDataset`, `data` is base64 encoded.
+    /// Chunk 0 starts the file over
+    UploadDatasetFile {
+        name: String,
+        file: String,
+        chunk: usize,
+        data: String,
+    },
+    /// Parses the uploaded files into dataset `name`. With `mdl_name` set
+    /// the sample sizes must match the model input and output layers
+    ImportDataset {
+        name: String,
+        #[serde(flatten)]
+        format: ImportFormat,
+        #[serde(default)]
+        mdl_name: Option<String>,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    /// `TrainModel` that runs in the background, answered with the job id
+    /// at once. The job keeps running after the client disconnects
+    SubmitTrainJob {
+        mdl_name: String,
+        data: TrainData,
+        #[serde(flatten)]
+        params: TrainParams,
+    },
+    JobStatus {
+        job_id: u64,
+    },
+    ListJobs,
+    /// `TrainResult` of a finished job, or the error it failed with
+    JobResult {
+        job_id: u64,
+    },
+}
+
+impl Request {
+    pub fn msg_type(&self) -> MessageType {
+        match self {
+            Request::Hello { .. } => MessageType::Hello,
+            Request::GetAvailableModels => MessageType::GetAvailableModels,
+            Request::GetLoadedModels => MessageType::GetLoadedModels,
+            Request::ModelInfo { .. } => MessageType::ModelInfo,
+            Request::CreateModel { .. } => MessageType::CreateModel,
+            Request::DeleteModel { .. } => MessageType::DeleteModel,
+            Request::RestoreModel { .. } => MessageType::RestoreModel,
+            Request::LoadModel { .. } => MessageType::LoadModel,
+            Request::UnloadModel { .. } => MessageType::UnloadModel,
+            Request::SaveModelCfg { .. } => MessageType::SaveModelCfg,
+            Request::SaveModelState { .. } => MessageType::SaveModelState,
+            Request::ListCheckpoints { .. } => MessageType::ListCheckpoints,
+            Request::TrainModel { .. } => MessageType::TrainModel,
+            Request::EvaluateData { .. } => MessageType::EvaluateData,
+            Request::UploadDataset { .. } => MessageType::UploadDataset,
+            Request::ListDatasets => MessageType::ListDatasets,
+            Request::DeleteDataset { .. } => MessageType::DeleteDataset,
+            Request::UploadDatasetFile { .. } => MessageType::UploadDatasetFile,
+            Request::ImportDataset { .. } => MessageType::ImportDataset,
+            Request::SubmitTrainJob { .. } => MessageType::SubmitTrainJob,
+            Request::JobStatus { .. } => MessageType::JobStatus,
+            Request::ListJobs => MessageType::ListJobs,
+            Request::JobResult { .. } => MessageType::JobResult,
+        }
+    }
+}
+
+/// Server -> client messages, tagged by `type` on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Response {
+    RespWelcome {
+        protocol_version: u32,
+        server_build: String,
+        supported: Vec<MessageType>,
+        /// Token or user name the connection is authenticated as
+        #[serde(default)]
+        authenticated_as: Option<String>,
+    },
+    RespModelCreateSuccess,
+    RespModelInfoSuccess {
+        mdl_info: ModelInfo,
+    },
+    RespAvailableModels {
+        available_mdls: Vec<String>,
+    },
+    RespLoadedModels {
+        loaded_mdls: Vec<String>,
+    },
+    RespLoadModel,
+    RespModelSaveCfg,
+    RespTrainModel {
+        result: TrainResult,
+    },
+    RespEvaluateData {
+        outputs: Vec<Vec<f32>>,
+    },
+    RespDeleteModel,
+    RespRestoreModel,
+    RespUnloadModel,
+    RespSaveModelState,
+    RespCheckpoints {
+        checkpoints: Vec<CheckpointInfo>,
+    },
+    /// Sent with `UNSOLICITED_ID` when the server stops,
+    /// requests already sent are still answered
+    RespServerShutdown,
+    /// Sent with `UNSOLICITED_ID`
+    RespEvent {
+        event: ServerEvent,
+    },
+    RespUploadDataset {
+        /// Samples received so far
+        samples: usize,
+        complete: bool,
+    },
+    RespDatasets {
+        datasets: Vec<DatasetInfo>,
+    },
+    RespDeleteDataset,
+    RespUploadDatasetFile {
+        /// Bytes of the file received so far
+        size: u64,
+    },
+    RespImportDataset {
+        info: DatasetInfo,
+    },
+    RespSubmitTrainJob {
+        job_id: u64,
+    },
+    RespJobStatus {
+        job: JobInfo,
+    },
+    RespJobs {
+        jobs: Vec<JobInfo>,
+    },
+    RespJobResult {
+        result: TrainResult,
+    },
+    /// Failure of any request, `code` is `NnioError::code`
+    RespError {
+        request_type: Option<MessageType>,
+        code: u32,
+        message: String,
+    },
+}
+
+impl Response {
+    pub fn msg_type(&self) -> MessageType {
+        match self {
+            Response::RespWelcome { .. } => MessageType::RespWelcome,
+            Response::RespModelCreateSuccess => MessageType::RespModelCreateSuccess,
+            Response::RespModelInfoSuccess { .. } => MessageType::RespModelInfoSuccess,
+            Response::RespAvailableModels { .. } => MessageType::RespAvailableModels,
+            Response::RespLoadedModels { .. } => MessageType::RespLoadedModels,
+            Response::RespLoadModel => MessageType::RespLoadModel,
+            Response::RespModelSaveCfg => MessageType::RespModelSaveCfg,
+            Response::RespTrainModel { .. } => MessageType::RespTrainModel,
+            Response::RespEvaluateData { .. } => MessageType::RespEvaluateData,
+            Response::RespDeleteModel => MessageType::RespDeleteModel,
+            Response::RespRestoreModel => MessageType::RespRestoreModel,
+            Response::RespUnloadModel => MessageType::RespUnloadModel,
+            Response::RespSaveModelState => MessageType::RespSaveModelState,
+            Response::RespCheckpoints { .. } => MessageType::RespCheckpoints,
+            Response::RespServerShutdown => MessageType::RespServerShutdown,
+            Response::RespEvent { .. } => MessageType::RespEvent,
+            Response::RespUploadDataset { .. } => MessageType::RespUploadDataset,
+            Response::RespDatasets { .. } => MessageType::RespDatasets,
+            Response::RespDeleteDataset => MessageType::RespDeleteDataset,
+            Response::RespUploadDatasetFile { .. } => MessageType::RespUploadDatasetFile,
+            Response::RespImportDataset { .. } => MessageType::RespImportDataset,
+            Response::RespSubmitTrainJob { .. } => MessageType::RespSubmitTrainJob,
+            Response::RespJobStatus { .. } => MessageType::RespJobStatus,
+            Response::RespJobs { .. } => MessageType::RespJobs,
+            Response::RespJobResult { .. } => MessageType::RespJobResult,
+            Response::RespError { .. } => MessageType::RespError,
+        }
+    }
+
+    pub fn error(request_type: Option<MessageType>, err: &NnioError) -> Self {
+        Response::RespError {
+            request_type,
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
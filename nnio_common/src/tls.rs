@@ -0,0 +1,61 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use rustls_pemfile::Item;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore};
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Reads every certificate of a PEM file
+pub fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let certs = rustls_pemfile::certs(&mut reader)?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "No certificates in {}",
+            path.display()
+        )));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first PKCS#8, RSA or EC private key of a PEM file
+pub fn load_private_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => {}
+        }
+    }
+
+    Err(invalid_data(format!(
+        "No private key in {}",
+        path.display()
+    )))
+}
+
+/// Trust store made of the CA certificates in a PEM file
+pub fn load_root_store(path: &Path) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in load_certs(path)? {
+        roots.add(&cert).map_err(invalid_data)?;
+    }
+
+    Ok(roots)
+}
@@ -0,0 +1,34 @@
+#!/bin/sh
+# Generates a self-signed CA plus server and client certificates for local TLS testing.
+#
+# usage : scripts/gen_certs.sh [out_dir] [server_host]
+set -e
+
+OUT=${1:-certs}
+HOST=${2:-localhost}
+DAYS=365
+
+mkdir -p "$OUT"
+cd "$OUT"
+
+# certificate authority
+openssl req -x509 -newkey rsa:2048 -nodes -days $DAYS \
+    -keyout ca.key -out ca.pem -subj "/CN=nnio test CA"
+
+# server certificate, valid for $HOST, localhost and 127.0.0.1
+openssl req -newkey rsa:2048 -nodes \
+    -keyout server.key -out server.csr -subj "/CN=$HOST"
+printf "subjectAltName=DNS:%s,DNS:localhost,IP:127.0.0.1\n" "$HOST" > server.ext
+openssl x509 -req -in server.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+    -days $DAYS -out server.pem -extfile server.ext
+
+# client certificate for mutual TLS
+openssl req -newkey rsa:2048 -nodes \
+    -keyout client.key -out client.csr -subj "/CN=nnio client"
+printf "extendedKeyUsage=clientAuth\n" > client.ext
+openssl x509 -req -in client.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+    -days $DAYS -out client.pem -extfile client.ext
+
+rm -f server.csr client.csr server.ext client.ext ca.srl
+
+echo "Certificates written to $(pwd)"